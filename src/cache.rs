@@ -1,17 +1,31 @@
 use crate::{
-    retrieve::{Post, SourcePath},
-    templates::markdown::{Blurb, Markdown},
+    cards,
+    check::check_alt_text,
+    config::Config,
+    diagnostics::Diagnostics,
+    images::Pictures,
+    links::check_sources,
+    meta::Meta,
+    navigation::{adjacent, Navigation, Neighbours, PostRef, Scope, Series},
+    output::Output,
+    persist::{content_hash, BuildCache, Rendered},
+    related::{count_terms, similarity},
+    retrieve::{slugify, Post, SourcePath},
+    tags::Tags,
+    taxonomy::Taxonomy,
+    templates::{
+        gallery::grid,
+        markdown::{Blurb, Content},
+        pages::redirect,
+    },
+    theme::Templates,
 };
 use anyhow::anyhow;
-use chrono::Local;
 use escaper::encode_minimal;
 use maud::html;
-use rss::{Channel, ChannelBuilder, Item, ItemBuilder};
+use rss::{CategoryBuilder, Channel, ChannelBuilder, Item, ItemBuilder};
 use std::{
-    collections::HashMap,
-    fs::{DirBuilder, File},
-    io::Write,
-    path::Path,
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::PathBuf,
     sync::Arc,
 };
@@ -21,15 +35,239 @@ trait ContentWatch: salsa::Database {
     #[salsa::input]
     fn file_path(&self, key: SourcePath) -> Arc<Post>;
 
+    /// The theme and layouts pages are rendered with.
+    #[salsa::input]
+    fn templates(&self) -> Arc<Templates>;
+
+    #[salsa::input]
+    fn config(&self) -> Arc<Config>;
+
+    /// Every loaded post, leaving out pages.
+    #[salsa::input]
+    fn posts(&self) -> Arc<Vec<SourcePath>>;
+
+    /// Output restored from the on-disk cache, if the source is unchanged.
+    #[salsa::input]
+    fn cached(&self, key: SourcePath) -> Option<Arc<Rendered>>;
+
+    /// Responsive versions of the images the post shows.
+    #[salsa::input]
+    fn pictures(&self, key: SourcePath) -> Arc<Pictures>;
+
+    /// Where the post's preview card is served from, once one is drawn.
+    #[salsa::input]
+    fn card(&self, key: SourcePath) -> Option<String>;
+
+    fn rendered(&self, key: SourcePath) -> Arc<Rendered>;
+
     fn to_html(&self, key: SourcePath) -> Arc<String>;
 
+    /// Posts in `scope`, oldest first.
+    fn chronological(&self, scope: Scope) -> Arc<Vec<SourcePath>>;
+
+    fn neighbours(&self, key: SourcePath, scope: Scope) -> Neighbours;
+
+    /// How often each word appears in the post's prose.
+    fn term_counts(&self, key: SourcePath) -> Arc<BTreeMap<String, u32>>;
+
+    /// How many posts each word appears in.
+    fn document_frequencies(&self) -> Arc<BTreeMap<String, u32>>;
+
+    /// The posts most related to this one, best first.
+    fn related(&self, key: SourcePath) -> Arc<Vec<PostRef>>;
+
+    /// Every post in the series `name`, in order.
+    fn series_parts(&self, name: String) -> Arc<Vec<SourcePath>>;
+
+    fn navigation(&self, key: SourcePath) -> Arc<Navigation>;
+
     fn tags(&self, key: SourcePath) -> Arc<Vec<String>>;
 }
 
+fn rendered(db: &dyn ContentWatch, key: SourcePath) -> Arc<Rendered> {
+    if let Some(rendered) = db.cached(key.clone()) {
+        return rendered;
+    }
+    let post = db.file_path(key.clone());
+    let config = db.config();
+    let content = Content {
+        markdown: &post.content,
+        post: Some(post.as_ref()),
+        pictures: &db.pictures(key),
+        cloudinary: Some(&config.cloudinary),
+        captions: config.captions,
+    };
+    let gallery = if post.frontmatter.gallery.is_empty() {
+        String::new()
+    } else {
+        grid(&post.frontmatter.gallery, Some(&config.cloudinary)).into_string()
+    };
+    Arc::new(Rendered {
+        content: html! {(content)}.into_string(),
+        blurb: html! {(Blurb(&post.content))}.into_string(),
+        gallery,
+    })
+}
+
 fn to_html(db: &dyn ContentWatch, key: SourcePath) -> Arc<String> {
     // Read the input string:
-    let input_string = db.file_path(key);
-    Arc::new(input_string.as_html().into_string())
+    let input_string = db.file_path(key.clone());
+    let rendered = db.rendered(key.clone());
+    let card = db.card(key.clone());
+    let navigation = db.navigation(key);
+    let meta = Meta::post(&db.config(), &input_string, card.as_deref());
+    Arc::new(
+        db.templates()
+            .render(&input_string, &rendered, &navigation, &meta)
+            .into_string(),
+    )
+}
+
+fn chronological(db: &dyn ContentWatch, scope: Scope) -> Arc<Vec<SourcePath>> {
+    let mut posts = db
+        .posts()
+        .iter()
+        .map(|path| db.file_path(path.clone()))
+        .filter(|post| match scope {
+            Scope::All => true,
+            Scope::Section(ref section) => post.filename.section().as_ref() == Some(section),
+            Scope::Tag(ref tag) => post.frontmatter.tags.contains(tag),
+        })
+        .collect::<Vec<_>>();
+    posts.sort_by(|a, b| (a.date, &a.filename.0).cmp(&(b.date, &b.filename.0)));
+    Arc::new(posts.iter().map(|post| post.filename.clone()).collect())
+}
+
+// Only the posts either side of a new or changed post see a different value
+// here, so every other page keeps its rendered HTML.
+fn neighbours(db: &dyn ContentWatch, key: SourcePath, scope: Scope) -> Neighbours {
+    let posts = db.chronological(scope);
+    let (previous, next) = adjacent(&posts, &key);
+    let link = |path: &SourcePath| PostRef::new(&db.file_path(path.clone()));
+    Neighbours {
+        previous: previous.map(link),
+        next: next.map(link),
+    }
+}
+
+fn term_counts(db: &dyn ContentWatch, key: SourcePath) -> Arc<BTreeMap<String, u32>> {
+    Arc::new(count_terms(&db.file_path(key).content))
+}
+
+fn document_frequencies(db: &dyn ContentWatch) -> Arc<BTreeMap<String, u32>> {
+    let mut frequencies = BTreeMap::new();
+    for path in db.posts().iter() {
+        for word in db.term_counts(path.clone()).keys() {
+            *frequencies.entry(word.clone()).or_insert(0) += 1;
+        }
+    }
+    Arc::new(frequencies)
+}
+
+/// Each shared tag scores a point and similar text up to one more, so tags
+/// lead and the text breaks ties between posts sharing as many of them.
+fn related(db: &dyn ContentWatch, key: SourcePath) -> Arc<Vec<PostRef>> {
+    let config = db.config();
+    if config.related.count == 0 {
+        return Arc::new(Vec::new());
+    }
+    let post = db.file_path(key.clone());
+    let posts = db.posts();
+    let frequencies = if config.related.content {
+        Some(db.document_frequencies())
+    } else {
+        None
+    };
+    let mut scored = posts
+        .iter()
+        .filter(|path| **path != key)
+        .map(|path| {
+            let other = db.file_path(path.clone());
+            let shared = other
+                .frontmatter
+                .tags
+                .iter()
+                .filter(|tag| post.frontmatter.tags.contains(tag))
+                .count();
+            let similar = frequencies.as_ref().map_or(0.0, |frequencies| {
+                similarity(
+                    &db.term_counts(key.clone()),
+                    &db.term_counts(path.clone()),
+                    frequencies,
+                    posts.len(),
+                )
+            });
+            (shared as f64 + similar, other)
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .collect::<Vec<_>>();
+    scored.sort_by(|(a, a_post), (b, b_post)| {
+        b.partial_cmp(a)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b_post.date.cmp(&a_post.date))
+    });
+    Arc::new(
+        scored
+            .iter()
+            .take(config.related.count)
+            .map(|(_, other)| PostRef::new(other))
+            .collect(),
+    )
+}
+
+fn series_parts(db: &dyn ContentWatch, name: String) -> Arc<Vec<SourcePath>> {
+    let mut parts = db
+        .posts()
+        .iter()
+        .map(|path| db.file_path(path.clone()))
+        .filter(|post| post.frontmatter.series.as_ref() == Some(&name))
+        .collect::<Vec<_>>();
+    parts.sort_by(|a, b| {
+        let order = |post: &Post| post.frontmatter.series_order.unwrap_or(u32::MAX);
+        (order(a), a.date, &a.filename.0).cmp(&(order(b), b.date, &b.filename.0))
+    });
+    Arc::new(parts.iter().map(|post| post.filename.clone()).collect())
+}
+
+fn navigation(db: &dyn ContentWatch, key: SourcePath) -> Arc<Navigation> {
+    let post = db.file_path(key.clone());
+    if post.is_page() {
+        return Arc::new(Navigation::default());
+    }
+    let section = key.section();
+    let in_section = section
+        .as_ref()
+        .map(|section| db.neighbours(key.clone(), Scope::Section(section.clone())))
+        .unwrap_or_default();
+    let tags = post
+        .frontmatter
+        .tags
+        .iter()
+        .map(|tag| {
+            let neighbours = db.neighbours(key.clone(), Scope::Tag(tag.clone()));
+            (tag.clone(), neighbours)
+        })
+        .collect();
+    let series = post.frontmatter.series.as_ref().map(|name| {
+        let parts = db.series_parts(name.clone());
+        Series {
+            name: name.clone(),
+            url: Series::url(name),
+            part: parts.iter().position(|path| *path == key).unwrap_or(0) + 1,
+            parts: parts
+                .iter()
+                .map(|path| PostRef::new(&db.file_path(path.clone())))
+                .collect(),
+        }
+    });
+    Arc::new(Navigation {
+        all: db.neighbours(key.clone(), Scope::All),
+        section,
+        in_section,
+        tags,
+        related: db.related(key).to_vec(),
+        series,
+    })
 }
 
 fn tags(db: &dyn ContentWatch, key: SourcePath) -> Arc<Vec<String>> {
@@ -39,85 +277,368 @@ fn tags(db: &dyn ContentWatch, key: SourcePath) -> Arc<Vec<String>> {
 }
 
 #[salsa::database(ContentWatchStorage)]
-#[derive(Default)]
 pub struct PostsDatabase {
     storage: salsa::Storage<Self>,
 }
 
 impl salsa::Database for PostsDatabase {}
 
+/// A post as the listings show it, with its rendered output so they use the
+/// cached blurb instead of rendering the Markdown again.
+#[derive(Debug, Clone)]
+pub struct Listed {
+    pub post: Arc<Post>,
+    pub rendered: Arc<Rendered>,
+}
+
+impl std::ops::Deref for Listed {
+    type Target = Post;
+
+    fn deref(&self) -> &Post {
+        &self.post
+    }
+}
+
+impl Default for PostsDatabase {
+    fn default() -> Self {
+        PostsDatabase::new(Arc::new(Templates::default()), Arc::new(Config::default()))
+    }
+}
+
 impl PostsDatabase {
-    pub fn add_posts(&mut self, paths: &[SourcePath]) -> Result<(), anyhow::Error> {
+    pub fn new(templates: Arc<Templates>, config: Arc<Config>) -> Self {
+        let mut db = PostsDatabase {
+            storage: salsa::Storage::default(),
+        };
+        db.set_templates(templates);
+        db.set_config(config);
+        db.set_posts(Arc::new(Vec::new()));
+        db
+    }
+
+    /// Loads every path into the database, returning the paths that loaded
+    /// along with an error for each that did not, and warnings about those
+    /// that did. Tags are filed under their canonical slugs.
+    pub fn add_posts(
+        &mut self,
+        paths: &[SourcePath],
+        cache: &mut BuildCache,
+        tags: &Tags,
+    ) -> (Vec<SourcePath>, Diagnostics) {
+        let mut posts = Vec::with_capacity(paths.len());
+        let mut diagnostics = Diagnostics::default();
+        for path in paths.iter() {
+            match Post::new(path.clone()) {
+                Ok(post) => posts.push(post),
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+        // Known only once every post is read, as frontmatter can make a file
+        // a page
+        let published = posts
+            .iter()
+            .map(|post| (post.filename.0.clone(), post.public_path(false)))
+            .collect::<BTreeMap<_, _>>();
+        let mut loaded = Vec::with_capacity(posts.len());
+        for mut post in posts.into_iter() {
+            // Links to posts that aren't there are reported like broken
+            // links in the output, without leaving the post out
+            diagnostics.extend(check_sources(&post, paths));
+            diagnostics.extend(check_alt_text(&post));
+            post.linked = post
+                .linked_sources()
+                .into_iter()
+                .filter_map(|source| {
+                    let public = published.get(&source.0)?.clone();
+                    Some((source.0, public))
+                })
+                .collect();
+            post.frontmatter.tags = tags.canonicalize(&post.frontmatter.tags);
+            post.tag_names = post
+                .frontmatter
+                .tags
+                .iter()
+                .map(|tag| (tag.clone(), tags.name(tag).to_string()))
+                .collect();
+            let path = post.filename.clone();
+            self.set_file_path(path.clone(), Arc::new(post));
+            self.set_pictures(path.clone(), Arc::new(Pictures::new()));
+            self.set_card(path.clone(), None);
+            self.set_cached(path.clone(), cache.get(&self.cache_key(&path)));
+            loaded.push(path);
+        }
+        let (posts, _pages) = self.partition_pages(&loaded);
+        self.set_posts(Arc::new(posts));
+        (loaded, diagnostics)
+    }
+
+    pub fn post(&self, path: &SourcePath) -> Arc<Post> {
+        self.file_path(path.clone())
+    }
+
+    pub fn site_config(&self) -> Arc<Config> {
+        self.config()
+    }
+
+    /// Shows the post's images with `pictures`, restoring its output from
+    /// `cache` if it was rendered with these same images before.
+    pub fn add_pictures(&mut self, path: &SourcePath, pictures: Pictures, cache: &mut BuildCache) {
+        if pictures.is_empty() {
+            return;
+        }
+        self.set_pictures(path.clone(), Arc::new(pictures));
+        self.set_cached(path.clone(), cache.get(&self.cache_key(path)));
+    }
+
+    /// What the post's output is saved under: the hash of its source, of
+    /// the whole config and templates it is rendered with, of the images it
+    /// shows and of where the posts it links to are, any of which can change
+    /// its markup.
+    fn cache_key(&self, path: &SourcePath) -> String {
+        let post = self.file_path(path.clone());
+        // Relative links are rewritten against where the post is
+        let mut parts = vec![
+            post.hash.clone(),
+            post.filename.0.to_string_lossy().to_string(),
+            serde_json::to_string(self.config().as_ref()).unwrap_or_default(),
+            self.templates().identity().to_string(),
+        ];
+        parts.extend(
+            self.pictures(path.clone())
+                .values()
+                .map(|picture| picture.hash.clone()),
+        );
+        parts.extend(
+            post.linked
+                .values()
+                .map(|public| public.0.to_string_lossy().to_string()),
+        );
+        content_hash(&parts.join("\0"))
+    }
+
+    /// Shares the card drawn for the post at `path` when it has no image of
+    /// its own.
+    pub fn add_card(&mut self, path: &SourcePath) {
+        let url = cards::url(&self.file_path(path.clone()));
+        self.set_card(path.clone(), Some(url));
+    }
+
+    /// Where the post's preview card is, if one was drawn.
+    #[cfg(feature = "runtime-templates")]
+    pub fn card_url(&self, path: &SourcePath) -> Option<String> {
+        self.card(path.clone())
+    }
+
+    /// The post's body and blurb as HTML.
+    #[cfg(feature = "runtime-templates")]
+    pub fn content(&self, path: &SourcePath) -> Arc<Rendered> {
+        self.rendered(path.clone())
+    }
+
+    /// The posts around the post.
+    #[cfg(feature = "runtime-templates")]
+    pub fn post_navigation(&self, path: &SourcePath) -> Arc<Navigation> {
+        self.navigation(path.clone())
+    }
+
+    /// The whole page for the post, rendered with the built-in templates.
+    #[cfg(feature = "runtime-templates")]
+    pub fn html(&self, path: &SourcePath) -> Arc<String> {
+        self.to_html(path.clone())
+    }
+
+    /// Saves every post's rendered output so the next run can skip it.
+    pub fn persist(
+        &self,
+        paths: &[SourcePath],
+        cache: &mut BuildCache,
+    ) -> Result<(), anyhow::Error> {
         for path in paths.iter() {
-            self.set_file_path(path.clone(), Arc::new(Post::new(path.clone())?));
+            cache.insert(&self.cache_key(path), &self.rendered(path.clone()))?;
         }
         Ok(())
     }
 
+    /// Splits `paths` into (posts, pages).
+    pub fn partition_pages(&self, paths: &[SourcePath]) -> (Vec<SourcePath>, Vec<SourcePath>) {
+        paths
+            .iter()
+            .cloned()
+            .partition(|path| !self.file_path(path.clone()).is_page())
+    }
+
+    /// Writes posts under `public/posts` and pages at the top of `public`.
     pub fn write_posts_to_file(
         &mut self,
         paths: &[SourcePath],
-        in_directory: &'static str,
+        out: &mut Output,
     ) -> Result<(), anyhow::Error> {
-        if !Path::new(in_directory).exists() {
-            DirBuilder::new().recursive(true).create(in_directory)?;
-        }
         for path in paths.iter() {
             let post = self.file_path(path.clone());
             let html = self.to_html(path.clone());
-            let mut path = PathBuf::from(in_directory);
-            let file_name = post.filename.0.file_name().unwrap();
-            path.push(file_name);
-            path.set_extension("html");
-            let mut file = File::create(&path)?;
-            file.write_all(html.as_bytes())?;
+            out.write(&post.public_path(true).0, html.as_bytes())?;
         }
         Ok(())
     }
 
-    pub fn five_most_recent(&self, paths: &[SourcePath]) -> Vec<Arc<Post>> {
+    /// `path` with its rendered output, for the listings.
+    pub fn listed(&self, path: &SourcePath) -> Listed {
+        Listed {
+            post: self.file_path(path.clone()),
+            rendered: self.rendered(path.clone()),
+        }
+    }
+
+    /// Writes a redirect to each of `paths` at every one of its `aliases`.
+    pub fn write_aliases(
+        &self,
+        paths: &[SourcePath],
+        out: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        let config = self.config();
+        for path in paths.iter() {
+            let post = self.file_path(path.clone());
+            let to = format!("/{}", post.public_path(false).0.display());
+            for alias in post.alias_paths() {
+                out.write(alias, redirect(&config, &to).into_string().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the links in `paths` to Markdown files that aren't published
+    /// point once rendered. `check_sources` reports these already.
+    pub fn unpublished_links(&self, paths: &[SourcePath]) -> BTreeSet<PathBuf> {
+        let mut targets = BTreeSet::new();
+        for path in paths {
+            let post = self.file_path(path.clone());
+            for source in post.linked_sources() {
+                if !post.linked.contains_key(&source.0) {
+                    targets.insert(PathBuf::from("public").join(source.to_public_path(false).0));
+                }
+            }
+        }
+        targets
+    }
+
+    pub fn five_most_recent(&self, paths: &[SourcePath]) -> Vec<Listed> {
         let mut posts = paths
             .iter()
-            .map(|path| {
-                let post: Arc<Post> = self.file_path(path.clone());
-                post
-            })
+            .map(|path| self.listed(path))
             .collect::<Vec<_>>();
-        posts.sort_by(|a, b| b.frontmatter.date.cmp(&a.frontmatter.date));
+        posts.sort_by(|a, b| b.date.cmp(&a.date));
         posts.into_iter().take(5).collect()
     }
 
-    pub fn get_tags(&self, paths: &[SourcePath]) -> HashMap<String, Vec<Arc<Post>>> {
-        let mut map: HashMap<String, Vec<Arc<Post>>> = HashMap::new();
+    /// Every term of `taxonomy` any of `paths` is filed under, with the
+    /// posts filed under it or anything nested in it.
+    pub fn get_terms(
+        &self,
+        taxonomy: &Taxonomy,
+        paths: &[SourcePath],
+    ) -> BTreeMap<String, Vec<Listed>> {
+        let mut map: BTreeMap<String, Vec<Listed>> = BTreeMap::new();
         for path in paths {
-            let post = self.file_path(path.clone());
-            for tag in post.frontmatter.tags.iter() {
-                let list = map.entry(tag.clone()).or_default();
+            let post = self.listed(path);
+            for term in taxonomy.terms(&post) {
+                let list = map.entry(term).or_default();
                 list.push(post.clone());
             }
         }
         map
     }
 
+    /// How to show each term of `taxonomy`: tags as `tags` says, and the
+    /// terms of any other taxonomy as first written in one of `paths`.
+    pub fn term_names(&self, taxonomy: &Taxonomy, tags: &Tags, paths: &[SourcePath]) -> Tags {
+        if taxonomy.is_tags() {
+            return tags.clone();
+        }
+        let mut names = BTreeMap::new();
+        for path in paths {
+            let post = self.file_path(path.clone());
+            for term in taxonomy.written(&post).unwrap_or_default() {
+                // Each level of a nested term is named by its own part
+                let mut slug = String::new();
+                for part in term.split('/').map(str::trim) {
+                    let part_slug = slugify(part);
+                    if part_slug.is_empty() {
+                        continue;
+                    }
+                    if !slug.is_empty() {
+                        slug.push('/');
+                    }
+                    slug.push_str(&part_slug);
+                    names
+                        .entry(slug.clone())
+                        .or_insert_with(|| part.to_string());
+                }
+            }
+        }
+        Tags::named(names)
+    }
+
+    /// Every series any of `paths` is part of, with all of its parts in
+    /// order.
+    pub fn get_series(&self, paths: &[SourcePath]) -> BTreeMap<String, Vec<Listed>> {
+        paths
+            .iter()
+            .filter_map(|path| self.file_path(path.clone()).frontmatter.series.clone())
+            .map(|name| {
+                let parts = self
+                    .series_parts(name.clone())
+                    .iter()
+                    .map(|path| self.listed(path))
+                    .collect();
+                (name, parts)
+            })
+            .collect()
+    }
+
     pub fn generate_rss(&self, paths: &[SourcePath]) -> Result<Channel, anyhow::Error> {
+        let config = self.config();
+        self.generate_feed(&config.title, &config.base_url, &config.description, paths)
+    }
+
+    /// A feed of `paths`, for the page at `link`.
+    fn generate_feed(
+        &self,
+        title: &str,
+        link: &str,
+        description: &str,
+        paths: &[SourcePath],
+    ) -> Result<Channel, anyhow::Error> {
         let mut items: Vec<Item> = Vec::new();
+        // The feed only changes when a post does, so rebuilding without
+        // content changes leaves feed.xml untouched.
+        let last_build_date = self
+            .five_most_recent(paths)
+            .first()
+            .map(|post| post.date.to_rfc2822())
+            .unwrap_or_default();
+        let config = self.config();
         for path in paths.iter() {
             let post = self.file_path(path.clone());
-            let link = format!(
-                "https://dustinknopoff.dev/{}",
-                post.filename.to_public_path(false).0.to_str().unwrap()
-            );
+            let rendered = self.rendered(path.clone());
+            let link = config.url(post.public_path(false).0.to_str().unwrap());
+            let mut categories = Vec::new();
+            if let Some(ref series) = post.frontmatter.series {
+                categories.push(
+                    CategoryBuilder::default()
+                        .name(series.clone())
+                        .domain(config.url(&Series::url(series)))
+                        .build()
+                        .map_err(|x| anyhow!("{}", x))?,
+                );
+            }
             items.push(
                 ItemBuilder::default()
                     .title(post.frontmatter.title.clone())
+                    .categories(categories)
                     .link(link)
-                    .pub_date(post.frontmatter.date.to_rfc2822())
-                    .description(encode_minimal(
-                        &html! {(Blurb(&post.content))}.into_string(),
-                    ))
-                    .content(encode_minimal(
-                        &html! {(Markdown(&post.content))}.into_string(),
-                    ))
+                    .pub_date(post.date.to_rfc2822())
+                    .description(encode_minimal(&rendered.blurb))
+                    .content(encode_minimal(&rendered.content))
                     .build()
                     .map_err(|x| anyhow!("{}", x))?,
             );
@@ -140,10 +661,10 @@ impl PostsDatabase {
         };
         use rss::validation::Validate;
         let channel = ChannelBuilder::default()
-            .title("Dustin Knopoff")
-            .link("http://dustinknopoff.dev")
-            .description("rustacean, cook, and martial arts enthusiast")
-            .last_build_date(Local::now().to_rfc2822())
+            .title(title.to_string())
+            .link(link.to_string())
+            .description(description.to_string())
+            .last_build_date(last_build_date)
             .items(items)
             .namespaces(namespaces)
             .build()
@@ -152,9 +673,305 @@ impl PostsDatabase {
         Ok(channel)
     }
 
-    pub fn rss_to_file(&self, channel: Channel) -> Result<(), anyhow::Error> {
-        let mut file = File::create("public/feed.xml")?;
-        file.write_all(channel.to_string().as_bytes())?;
+    pub fn rss_to_file(&self, channel: Channel, out: &mut Output) -> Result<(), anyhow::Error> {
+        out.write("public/feed.xml", channel.to_string().as_bytes())
+    }
+
+    /// Writes a feed for each of `terms` next to its page, named after it
+    /// with `names`.
+    pub fn term_feeds_to_file(
+        &self,
+        taxonomy: &Taxonomy,
+        names: &Tags,
+        terms: &BTreeMap<String, Vec<Listed>>,
+        out: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        let config = self.config();
+        for (term, posts) in terms.iter() {
+            let paths = posts
+                .iter()
+                .map(|post| post.filename.clone())
+                .collect::<Vec<_>>();
+            let channel = self.generate_feed(
+                &format!("{} | {}", config.title, names.name(term)),
+                &config.url(&taxonomy.url(term)),
+                names.description(term).unwrap_or(&config.description),
+                &paths,
+            )?;
+            out.write(
+                format!("public{}", taxonomy.feed_url(term)),
+                channel.to_string().as_bytes(),
+            )?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        images::Picture,
+        retrieve::{FrontMatter, PublicPath},
+    };
+    use chrono::DateTime;
+    use std::path::{Path, PathBuf};
+
+    fn post(hash: &str) -> Post {
+        Post {
+            frontmatter: FrontMatter::default(),
+            content: String::from("Hello"),
+            filename: SourcePath(PathBuf::from("content/rust/hello.md")),
+            hash: hash.to_string(),
+            date: DateTime::parse_from_rfc3339("2021-03-04T00:00:00-05:00").unwrap(),
+            tag_names: BTreeMap::new(),
+            written_tags: Vec::new(),
+            linked: BTreeMap::new(),
+        }
+    }
+
+    /// The cache key of `post` shown with `pictures`, in a site of `config`.
+    fn key(config: Config, post: Post, pictures: Pictures) -> String {
+        let mut db = PostsDatabase::new(Arc::new(Templates::default()), Arc::new(config));
+        let path = post.filename.clone();
+        db.set_file_path(path.clone(), Arc::new(post));
+        db.set_pictures(path.clone(), Arc::new(pictures));
+        db.cache_key(&path)
+    }
+
+    fn picture(hash: &str) -> Picture {
+        Picture {
+            width: 10,
+            height: 10,
+            hash: hash.to_string(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// A post at `path` in `content/`, dated `day` of March 2021 and tagged
+    /// `tags`.
+    fn dated(path: &str, day: u32, tags: &[&str]) -> Post {
+        let date = format!("2021-03-{:02}T00:00:00-05:00", day);
+        let mut post = post(path);
+        post.filename = SourcePath(PathBuf::from("content").join(path));
+        post.date = DateTime::parse_from_rfc3339(&date).unwrap();
+        post.frontmatter.title = path.to_string();
+        post.frontmatter.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        post
+    }
+
+    /// A database holding `posts`, as loading them would leave it.
+    fn site(posts: Vec<Post>) -> PostsDatabase {
+        let mut db = PostsDatabase::default();
+        let paths = posts
+            .into_iter()
+            .map(|post| {
+                let path = post.filename.clone();
+                db.set_file_path(path.clone(), Arc::new(post));
+                db.set_pictures(path.clone(), Arc::new(Pictures::new()));
+                db.set_cached(path.clone(), None);
+                db.set_card(path.clone(), None);
+                path
+            })
+            .collect::<Vec<_>>();
+        let (posts, _pages) = db.partition_pages(&paths);
+        db.set_posts(Arc::new(posts));
+        db
+    }
+
+    fn path(path: &str) -> SourcePath {
+        SourcePath(PathBuf::from("content").join(path))
+    }
+
+    fn link(path: &str) -> Option<PostRef> {
+        let name = Path::new(path).file_stem().unwrap().to_string_lossy();
+        Some(PostRef {
+            title: path.to_string(),
+            url: format!("/posts/{}.html", name),
+        })
+    }
+
+    #[test]
+    fn cache_key_is_stable() {
+        assert_eq!(
+            key(Config::default(), post("a"), Pictures::new()),
+            key(Config::default(), post("a"), Pictures::new())
+        );
+    }
+
+    #[test]
+    fn cache_key_changes_with_what_the_output_depends_on() {
+        let base = key(Config::default(), post("a"), Pictures::new());
+        assert_ne!(base, key(Config::default(), post("b"), Pictures::new()));
+
+        let moved = Post {
+            filename: SourcePath(PathBuf::from("content/design/hello.md")),
+            ..post("a")
+        };
+        assert_ne!(base, key(Config::default(), moved, Pictures::new()));
+
+        let config = Config {
+            title: String::from("Another site"),
+            ..Config::default()
+        };
+        assert_ne!(base, key(config, post("a"), Pictures::new()));
+
+        let mut pictures = Pictures::new();
+        pictures.insert(String::from("cat.jpg"), picture("1"));
+        let with_picture = key(Config::default(), post("a"), pictures.clone());
+        assert_ne!(base, with_picture);
+        pictures.insert(String::from("cat.jpg"), picture("2"));
+        assert_ne!(with_picture, key(Config::default(), post("a"), pictures));
+
+        let mut linked = post("a");
+        linked.linked.insert(
+            PathBuf::from("content/rust/other.md"),
+            PublicPath(PathBuf::from("posts/other.html")),
+        );
+        let linking = key(Config::default(), linked.clone(), Pictures::new());
+        assert_ne!(base, linking);
+        linked.linked.insert(
+            PathBuf::from("content/rust/other.md"),
+            PublicPath(PathBuf::from("other.html")),
+        );
+        assert_ne!(linking, key(Config::default(), linked, Pictures::new()));
+    }
+
+    #[test]
+    fn neighbours_are_in_date_order_within_each_scope() {
+        let db = site(vec![
+            dated("rust/one.md", 1, &["cli"]),
+            dated("design/two.md", 2, &["cli"]),
+            dated("rust/three.md", 3, &[]),
+            dated("rust/four.md", 4, &["cli"]),
+            dated("pages/about.md", 5, &["cli"]),
+        ]);
+        let navigation = db.navigation(path("rust/three.md"));
+        assert_eq!(navigation.all.previous, link("design/two.md"));
+        assert_eq!(navigation.all.next, link("rust/four.md"));
+        assert_eq!(navigation.section.as_deref(), Some("rust"));
+        assert_eq!(navigation.in_section.previous, link("rust/one.md"));
+        assert_eq!(navigation.in_section.next, link("rust/four.md"));
+        assert!(navigation.tags.is_empty());
+
+        let navigation = db.navigation(path("rust/four.md"));
+        assert_eq!(navigation.all.next, None);
+        let cli = &navigation.tags["cli"];
+        assert_eq!(cli.previous, link("design/two.md"));
+        // Pages aren't posts, so they aren't anyone's neighbour
+        assert_eq!(cli.next, None);
+
+        let navigation = db.navigation(path("rust/one.md"));
+        assert_eq!(navigation.all.previous, None);
+        assert_eq!(navigation.tags["cli"].next, link("design/two.md"));
+    }
+
+    #[test]
+    fn pages_have_no_navigation() {
+        let db = site(vec![
+            dated("rust/one.md", 1, &[]),
+            dated("pages/about.md", 2, &[]),
+        ]);
+        assert_eq!(
+            *db.navigation(path("pages/about.md")),
+            Navigation::default()
+        );
+    }
+
+    #[test]
+    fn series_parts_follow_series_order_then_date() {
+        let part = |path: &str, day: u32, order: Option<u32>| {
+            let mut post = dated(path, day, &[]);
+            post.frontmatter.series = Some(String::from("Bashdoc"));
+            post.frontmatter.series_order = order;
+            post
+        };
+        let db = site(vec![
+            part("rust/late.md", 9, None),
+            part("rust/second.md", 1, Some(2)),
+            part("rust/first.md", 5, Some(1)),
+            part("rust/early.md", 2, None),
+            dated("rust/other.md", 3, &[]),
+        ]);
+        assert_eq!(
+            *db.series_parts(String::from("Bashdoc")),
+            vec![
+                path("rust/first.md"),
+                path("rust/second.md"),
+                path("rust/early.md"),
+                path("rust/late.md"),
+            ]
+        );
+
+        let series = db.navigation(path("rust/early.md")).series.clone().unwrap();
+        assert_eq!(series.name, "Bashdoc");
+        assert_eq!(series.url, "/series/bashdoc.html");
+        assert_eq!(series.part, 3);
+        assert_eq!(series.parts.len(), 4);
+        assert_eq!(Some(series.parts[0].clone()), link("rust/first.md"));
+        assert!(db.navigation(path("rust/other.md")).series.is_none());
+
+        let listed = db.get_series(&[path("rust/late.md"), path("rust/other.md")]);
+        assert_eq!(listed.keys().collect::<Vec<_>>(), vec!["Bashdoc"]);
+        assert_eq!(listed["Bashdoc"][0].filename, path("rust/first.md"));
+    }
+
+    #[test]
+    fn related_posts_share_tags_first_then_wording() {
+        let mut db = site(vec![
+            dated("rust/post.md", 1, &["rust", "cli"]),
+            dated("rust/both.md", 2, &["rust", "cli"]),
+            dated("rust/one.md", 3, &["rust"]),
+            dated("rust/newer-one.md", 4, &["rust"]),
+            dated("design/none.md", 5, &["design"]),
+        ]);
+        let related = db.related(path("rust/post.md"));
+        assert_eq!(
+            related
+                .iter()
+                .map(|post| post.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "/posts/both.html",
+                "/posts/newer-one.html",
+                "/posts/one.html"
+            ]
+        );
+        db.set_config(Arc::new(Config {
+            related: crate::config::RelatedConfig {
+                count: 0,
+                content: true,
+            },
+            ..Config::default()
+        }));
+        assert!(db.related(path("rust/post.md")).is_empty());
+    }
+
+    #[test]
+    fn terms_are_named_as_first_written() {
+        let languages = Taxonomy {
+            name: String::from("languages"),
+            title: None,
+            cloud: false,
+        };
+        let mut first = dated("rust/first.md", 1, &[]);
+        first.frontmatter.extra.insert(
+            String::from("languages"),
+            serde_yaml::from_str("[Rust/Async IO]").unwrap(),
+        );
+        let mut second = dated("rust/second.md", 2, &[]);
+        second.frontmatter.extra.insert(
+            String::from("languages"),
+            serde_yaml::from_str("[rust, C++]").unwrap(),
+        );
+        let db = site(vec![first, second]);
+        let names = db.term_names(
+            &languages,
+            &Tags::default(),
+            &[path("rust/first.md"), path("rust/second.md")],
+        );
+        assert_eq!(names.name("rust"), "Rust");
+        assert_eq!(names.name("rust/async-io"), "Async IO");
+        assert_eq!(names.name("c"), "C++");
+    }
+}
@@ -0,0 +1,114 @@
+//! Social preview images for posts, shown as `og:image` when a post has no
+//! image of its own.
+use crate::{
+    cache::PostsDatabase,
+    diagnostics::{Diagnostic, Diagnostics},
+    output::Output,
+    persist::{content_hash, BuildCache},
+    retrieve::{Post, SourcePath},
+    templates::card::{card, HEIGHT, LOGO, WIDTH},
+};
+use std::{fs, path::PathBuf};
+use tiny_skia::{Pixmap, Transform};
+use url::Url;
+use usvg::{FitTo, Options, Tree};
+
+/// Fonts to draw text in when the one asked for isn't installed, best first.
+const FALLBACK_FONTS: &[&str] = &["Helvetica", "Arial", "DejaVu Sans", "Liberation Sans"];
+
+/// Where the card for `post` is served from, relative to the site root.
+pub fn url(post: &Post) -> String {
+    let mut path = PathBuf::from("/cards");
+    path.push(post.public_path(false).0);
+    path.set_extension("png");
+    path.to_string_lossy().to_string()
+}
+
+/// Writes a card for each of `paths`, returning the paths that got one.
+/// Cards whose title, tags, date and logo are unchanged are copied from
+/// `cache` instead of being drawn again. Posts whose card can't be drawn go
+/// without one and are reported.
+pub fn write_cards(
+    db: &PostsDatabase,
+    resource_dir: &str,
+    paths: &[SourcePath],
+    cache: &mut BuildCache,
+    out: &mut Output,
+) -> (Vec<SourcePath>, Diagnostics) {
+    let mut drawn = Vec::new();
+    let mut diagnostics = Diagnostics::default();
+    let config = db.site_config();
+    let site = Url::parse(&config.base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| config.base_url.clone());
+    let logo = fs::read_to_string(PathBuf::from(resource_dir).join(LOGO)).unwrap_or_default();
+    let mut options = None;
+    for path in paths.iter() {
+        let post = db.post(path);
+        let svg = card(&post, &site).into_string();
+        let hash = content_hash(&format!("{}\0{}", svg, logo));
+        let png = match cache.get_file(&hash, "png") {
+            Some(png) => Ok(png),
+            None => {
+                let options = options.get_or_insert_with(|| load_options(resource_dir));
+                render(&svg, options).and_then(|png| {
+                    // A dry run leaves the cache as it was, too
+                    if !out.is_dry_run() {
+                        cache.insert_file(&hash, "png", &png)?;
+                    }
+                    Ok(png)
+                })
+            }
+        };
+        match png.and_then(|png| out.write(format!("public{}", url(&post)), &png)) {
+            Ok(()) => drawn.push(path.clone()),
+            Err(e) => diagnostics.push(Diagnostic::new(
+                &path.0,
+                format!("couldn't draw a preview card: {}", e),
+            )),
+        }
+    }
+    (drawn, diagnostics)
+}
+
+/// Rendering options that find the logo in `resource_dir` and text in the
+/// fonts installed on this machine. Loading the fonts is slow, so this only
+/// happens when a card needs drawing.
+fn load_options(resource_dir: &str) -> Options {
+    let mut options = Options {
+        resources_dir: Some(PathBuf::from(resource_dir)),
+        ..Options::default()
+    };
+    options.fontdb.load_system_fonts();
+    // Text in a font that isn't installed, like the logo's, is drawn in the
+    // fallback font instead
+    let families = options
+        .fontdb
+        .faces()
+        .iter()
+        .map(|face| face.family.as_str())
+        .collect::<Vec<_>>();
+    match FALLBACK_FONTS
+        .iter()
+        .find(|family| families.contains(family))
+        .or_else(|| families.first())
+    {
+        Some(family) => options.font_family = family.to_string(),
+        None => log::warn!("No fonts found, so preview cards will have no text"),
+    }
+    options
+}
+
+fn render(svg: &str, options: &Options) -> Result<Vec<u8>, anyhow::Error> {
+    let tree = Tree::from_str(svg, &options.to_ref())?;
+    let mut pixmap = Pixmap::new(WIDTH, HEIGHT).ok_or_else(|| anyhow::anyhow!("Empty card"))?;
+    resvg::render(
+        &tree,
+        FitTo::Original,
+        Transform::default(),
+        pixmap.as_mut(),
+    )
+    .ok_or_else(|| anyhow::anyhow!("Nothing to draw"))?;
+    Ok(pixmap.encode_png()?)
+}
@@ -0,0 +1,449 @@
+use crate::{
+    cache::PostsDatabase,
+    diagnostics::{Diagnostic, Diagnostics},
+    gallery,
+    links::resolve,
+    retrieve::{Post, PublicPath, SourcePath},
+    taxonomy::term_slug,
+    templates::markdown::is_known_language,
+    theme::Templates,
+};
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::PathBuf,
+};
+use url::Url;
+
+/// Validates content beyond what parsing already guarantees, against the set
+/// of files a build would produce.
+pub fn check_posts(
+    db: &PostsDatabase,
+    layouts: &[&str],
+    paths: &[SourcePath],
+    produced: &BTreeSet<PathBuf>,
+) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let mut slugs: HashMap<PublicPath, &SourcePath> = HashMap::new();
+    let mut series_orders: HashMap<(String, u32), &SourcePath> = HashMap::new();
+    for path in paths.iter() {
+        let post = db.post(path);
+        let source = fs::read_to_string(&path.0).unwrap_or_default();
+        let error = |message: String, needle: &str| {
+            let diagnostic = Diagnostic::new(path.0.clone(), message);
+            match source.find(needle) {
+                Some(offset) => diagnostic.at(&source, offset),
+                None => diagnostic,
+            }
+        };
+
+        let public = post.public_path(false);
+        if let Some(other) = slugs.insert(public.clone(), path) {
+            diagnostics.push(Diagnostic::new(
+                path.0.clone(),
+                format!(
+                    "{} is also generated from {}",
+                    public.0.display(),
+                    other.0.display()
+                ),
+            ));
+        }
+
+        let layout = Templates::layout_name(&post);
+        if !layouts.contains(&layout) {
+            let diagnostic = Diagnostic::new(
+                path.0.clone(),
+                format!(
+                    "unknown layout `{}`, expected one of: {}",
+                    layout,
+                    layouts.join(", ")
+                ),
+            );
+            let matter = source
+                .get(..source.len().saturating_sub(post.content.len()))
+                .unwrap_or(&source);
+            diagnostics.push(match frontmatter_field(matter, "layout") {
+                Some(offset) => diagnostic.at(&source, offset),
+                None => diagnostic,
+            });
+        }
+
+        for taxonomy in db.site_config().all_taxonomies() {
+            // Tags as written, before they were made canonical
+            let written = if taxonomy.is_tags() {
+                Ok(post.written_tags.clone())
+            } else {
+                taxonomy.written(&post)
+            };
+            let terms = match written {
+                Ok(terms) => terms,
+                Err(message) => {
+                    diagnostics.push(error(message, &taxonomy.name));
+                    continue;
+                }
+            };
+            for term in terms.iter() {
+                if term_slug(term).is_empty() {
+                    let what = if taxonomy.is_tags() { "tag" } else { "term" };
+                    diagnostics.push(error(
+                        format!(
+                            "{} `{}` has no letters or digits to make a URL from",
+                            what, term
+                        ),
+                        term,
+                    ));
+                }
+            }
+        }
+
+        if let (None, Some(order)) = (&post.frontmatter.series, post.frontmatter.series_order) {
+            diagnostics.push(error(
+                format!("series_order {} given without a series", order),
+                "series_order",
+            ));
+        }
+        if let (Some(series), Some(order)) =
+            (&post.frontmatter.series, post.frontmatter.series_order)
+        {
+            if let Some(other) = series_orders.insert((series.clone(), order), path) {
+                diagnostics.push(error(
+                    format!(
+                        "{} is also part {} of the series `{}`",
+                        other.0.display(),
+                        order,
+                        series
+                    ),
+                    "series_order",
+                ));
+            }
+        }
+
+        if let Some(ref link) = post.frontmatter.link {
+            if let Err(e) = Url::parse(link) {
+                diagnostics.push(error(
+                    format!("link `{}` is not a valid URL: {}", link, e),
+                    link,
+                ));
+            }
+        }
+
+        for alias in post.frontmatter.aliases.iter() {
+            if !alias.starts_with('/') || alias.contains("://") {
+                diagnostics.push(error(
+                    format!(
+                        "alias `{}` should be a path from the site root, like `/posts/old.html`",
+                        alias
+                    ),
+                    alias,
+                ));
+            }
+        }
+
+        let body_start = source.find(post.content.as_str()).unwrap_or(0);
+        for (event, range) in Parser::new(&post.content).into_offset_iter() {
+            let problem = match event {
+                // Links to Markdown files were checked as the post was loaded
+                Event::Start(Tag::Link(_, ref dest, _)) if post.linked_source(dest).is_some() => {
+                    None
+                }
+                Event::Start(Tag::Link(_, ref dest, _))
+                | Event::Start(Tag::Image(_, ref dest, _)) => {
+                    match resolve(&public, &post.resolve(dest)) {
+                        Some((target, _)) if !produced.contains(&target) => {
+                            Some(format!("`{}` does not point at a generated file", dest))
+                        }
+                        _ => None,
+                    }
+                }
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang)))
+                    if !is_known_language(lang) =>
+                {
+                    Some(format!("unknown code block language `{}`", lang))
+                }
+                _ => None,
+            };
+            if let Some(message) = problem {
+                diagnostics.push(
+                    Diagnostic::new(path.0.clone(), message).at(&source, body_start + range.start),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Where the value of `key` is set in the YAML or TOML frontmatter `matter`,
+/// or the key itself when the value is on the lines below it.
+fn frontmatter_field(matter: &str, key: &str) -> Option<usize> {
+    let mut start = 0;
+    for line in matter.split_inclusive('\n') {
+        let key_start = line.len() - line.trim_start().len();
+        let rest = line[key_start..].strip_prefix(key).map(str::trim_start);
+        if let Some(value) = rest.and_then(|r| r.strip_prefix(':').or_else(|| r.strip_prefix('=')))
+        {
+            let value = value.trim_start();
+            if value.trim().is_empty() {
+                return Some(start + key_start);
+            }
+            return Some(start + line.len() - value.len());
+        }
+        start += line.len();
+    }
+    None
+}
+
+/// Warns about each image in `post` without alt text: in its body, in its
+/// `gallery` blocks and in its frontmatter `gallery`.
+pub fn check_alt_text(post: &Post) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let source = fs::read_to_string(&post.filename.0).unwrap_or_default();
+    let missing = |src: &str, offset: Option<usize>| {
+        let diagnostic = Diagnostic::new(
+            post.filename.0.clone(),
+            format!("image `{}` has no alt text", src),
+        )
+        .warning();
+        match offset {
+            Some(offset) => diagnostic.at(&source, offset),
+            None => diagnostic,
+        }
+    };
+    // Sources can also be written elsewhere in the frontmatter, as a `link`
+    let gallery_start = source.find("gallery").unwrap_or(0);
+    for image in post.frontmatter.gallery.iter() {
+        if image.alt.is_none() {
+            let offset = source[gallery_start..]
+                .find(image.src.as_str())
+                .map(|i| gallery_start + i);
+            diagnostics.push(missing(&image.src, offset));
+        }
+    }
+    let body_start = source.find(post.content.as_str()).unwrap_or(0);
+    let mut in_gallery = false;
+    // Where the image being read starts, and its alt text so far
+    let mut image: Option<(usize, String)> = None;
+    for (event, range) in Parser::new(&post.content).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang))) => {
+                in_gallery = lang.as_ref() == gallery::FENCE;
+            }
+            Event::End(Tag::CodeBlock(_)) => in_gallery = false,
+            Event::Text(ref text) if in_gallery => {
+                for shown in gallery::parse_block(text) {
+                    if shown.alt.is_none() {
+                        let offset = text
+                            .find(shown.src.as_str())
+                            .map(|i| body_start + range.start + i);
+                        diagnostics.push(missing(&shown.src, offset));
+                    }
+                }
+            }
+            Event::Start(Tag::Image(..)) => image = Some((range.start, String::new())),
+            Event::Text(ref text) | Event::Code(ref text) => {
+                if let Some((_, ref mut alt)) = image {
+                    alt.push_str(text);
+                }
+            }
+            Event::End(Tag::Image(_, ref dest, _)) => {
+                if let Some((start, alt)) = image.take() {
+                    if alt.trim().is_empty() {
+                        diagnostics.push(missing(dest, Some(body_start + start)));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{persist::BuildCache, tags::Tags};
+
+    /// An empty directory of the test's own.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portfolio-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Loads and checks `files`, written under a directory named `name`, as
+    /// though the build had produced `produced`.
+    fn check(name: &str, files: &[(&str, &str)], produced: &[&str]) -> Diagnostics {
+        let dir = scratch(name);
+        let mut paths = Vec::new();
+        for (path, source) in files {
+            let path = dir.join("content").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, source).unwrap();
+            paths.push(SourcePath(path));
+        }
+        let mut db = PostsDatabase::default();
+        let mut cache = BuildCache::open(dir.join("cache"));
+        let (loaded, mut diagnostics) = db.add_posts(&paths, &mut cache, &Tags::default());
+        let produced = produced.iter().map(PathBuf::from).collect();
+        diagnostics.extend(check_posts(&db, &["post", "page"], &loaded, &produced));
+        diagnostics
+    }
+
+    fn messages(diagnostics: &Diagnostics) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.message.as_str()).collect()
+    }
+
+    #[test]
+    fn a_clean_post_has_no_problems() {
+        let diagnostics = check(
+            "check-clean",
+            &[(
+                "rust/hello.md",
+                "---\ntitle: Hello\ndate: 2021-01-01\ntags: [rust]\n---\n\
+                 See [the other](/posts/other.html) post.\n\n```rust\nfn main() {}\n```\n",
+            )],
+            &["public/posts/other.html"],
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn posts_with_the_same_slug_collide() {
+        let post = "---\ndate: 2021-01-01\n---\nHi\n";
+        let diagnostics = check(
+            "check-slugs",
+            &[("rust/hello.md", post), ("design/hello.md", post)],
+            &[],
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert!(messages(&diagnostics)[0].starts_with("posts/hello.html is also generated from"));
+    }
+
+    #[test]
+    fn unknown_layouts_point_at_the_frontmatter() {
+        let diagnostics = check(
+            "check-layout",
+            &[(
+                "rust/fancy.md",
+                "---\ntitle: A fancy post\ndate: 2021-01-01\nlayout: fancy\n---\nVery fancy\n",
+            )],
+            &[],
+        );
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(
+            diagnostic.message,
+            "unknown layout `fancy`, expected one of: post, page"
+        );
+        assert_eq!(diagnostic.position, Some((4, 9)));
+    }
+
+    #[test]
+    fn frontmatter_fields_are_found_in_yaml_and_toml() {
+        let yaml = "title: layout\nlayouts: no\nlayout: fancy\n";
+        assert_eq!(frontmatter_field(yaml, "layout"), yaml.find("fancy"));
+        let toml = "title = \"layout\"\nlayout = \"fancy\"\n";
+        assert_eq!(frontmatter_field(toml, "layout"), toml.find("\"fancy"));
+        let nested = "gallery:\n  - cat.jpg\n";
+        assert_eq!(frontmatter_field(nested, "gallery"), Some(0));
+        assert_eq!(frontmatter_field(yaml, "series"), None);
+    }
+
+    #[test]
+    fn terms_are_checked_as_written() {
+        let diagnostics = check(
+            "check-terms",
+            &[(
+                "rust/hello.md",
+                "---\ndate: 2021-01-01\ntags: [rust, \"!!!\"]\n---\nHi\n",
+            )],
+            &[],
+        );
+        assert_eq!(
+            messages(&diagnostics),
+            vec!["tag `!!!` has no letters or digits to make a URL from"]
+        );
+    }
+
+    #[test]
+    fn series_parts_are_numbered_once() {
+        let part = |order: u32| {
+            format!(
+                "---\ndate: 2021-01-01\nseries: Bashdoc\nseries_order: {}\n---\nHi\n",
+                order
+            )
+        };
+        let diagnostics = check(
+            "check-series",
+            &[
+                ("rust/one.md", &part(1)),
+                ("rust/two.md", &part(1)),
+                (
+                    "rust/lonely.md",
+                    "---\ndate: 2021-01-01\nseries_order: 3\n---\nHi\n",
+                ),
+            ],
+            &[],
+        );
+        let messages = messages(&diagnostics);
+        assert_eq!(messages.len(), 2);
+        assert!(messages.contains(&"series_order 3 given without a series"));
+        assert!(messages
+            .iter()
+            .any(|m| m.ends_with("is also part 1 of the series `Bashdoc`")));
+    }
+
+    #[test]
+    fn body_links_and_code_blocks_are_checked() {
+        let diagnostics = check(
+            "check-body",
+            &[(
+                "rust/hello.md",
+                "---\ndate: 2021-01-01\nlink: not a url\n---\n\
+                 A [missing](/posts/missing.html) post, ![a cat](cat.png) \
+                 and [elsewhere](https://example.com).\n\n```klingon\nqapla'\n```\n",
+            )],
+            &["public/posts/cat.png"],
+        );
+        let messages = messages(&diagnostics);
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("link `not a url` is not a valid URL"));
+        assert_eq!(
+            messages[1],
+            "`/posts/missing.html` does not point at a generated file"
+        );
+        assert_eq!(messages[2], "unknown code block language `klingon`");
+        let line = diagnostics.iter().nth(1).unwrap().position.unwrap().0;
+        assert_eq!(line, 5);
+    }
+
+    #[test]
+    fn a_broken_link_to_markdown_is_reported_once() {
+        let diagnostics = check(
+            "check-markdown-links",
+            &[
+                (
+                    "rust/bashdoc1.md",
+                    "---\ndate: 2021-01-01\n---\n\
+                     See [part 2](./bashdoc2.md) and [part 3](./bashdoc3.md).\n",
+                ),
+                ("rust/bashdoc2.md", "---\ndate: 2021-01-02\n---\nHi\n"),
+            ],
+            &["public/posts/bashdoc1.html", "public/posts/bashdoc2.html"],
+        );
+        assert_eq!(
+            messages(&diagnostics),
+            vec![format!(
+                "`./bashdoc3.md` links to {}, which is not a post or page",
+                std::env::temp_dir()
+                    .join(format!(
+                        "portfolio-rs-check-markdown-links-{}",
+                        std::process::id()
+                    ))
+                    .join("content/rust/bashdoc3.md")
+                    .display()
+            )]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Sizing images hosted on Cloudinary by rewriting their URLs. Delivery URLs
+//! look like
+//!
+//! ```text
+//! https://res.cloudinary.com/<cloud>/image/upload/[<transformations>/...][v<version>/]<public id>
+//! ```
+//!
+//! and each set of transformations applies to the result of the one before,
+//! so one more set, to limit the width or crop a thumbnail, can follow
+//! whatever the author wrote.
+use crate::config::CloudinaryConfig;
+use url::Url;
+
+const HOST: &str = "res.cloudinary.com";
+
+/// Keys of transformation parameters, which tell a set of transformations
+/// apart from folders in the public ID.
+const PARAMETERS: &[&str] = &[
+    "a", "ac", "af", "ar", "b", "bo", "c", "co", "cs", "d", "dl", "dn", "dpr", "du", "e", "eo",
+    "f", "fl", "fn", "fps", "g", "h", "ki", "l", "o", "p", "pg", "q", "r", "so", "sp", "t", "u",
+    "vc", "vs", "w", "x", "y", "z",
+];
+
+/// `src` at each of the configured widths, narrowest first, or `None` when
+/// it isn't a Cloudinary image.
+pub fn versions(config: &CloudinaryConfig, src: &str) -> Option<Vec<(String, u32)>> {
+    if config.widths.is_empty() {
+        return None;
+    }
+    let mut widths = config.widths.clone();
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+        .into_iter()
+        .map(|width| {
+            let limit = with_defaults(config, &format!("c_limit,w_{}", width));
+            transform(src, &limit).map(|url| (url, width))
+        })
+        .collect()
+}
+
+/// `src` cropped to a `size` pixel square around what's interesting in it,
+/// or `None` when it isn't a Cloudinary image.
+pub fn thumbnail(config: &CloudinaryConfig, src: &str, size: u32) -> Option<String> {
+    if config.widths.is_empty() {
+        return None;
+    }
+    let fill = with_defaults(config, &format!("c_fill,g_auto,w_{0},h_{0}", size));
+    transform(src, &fill)
+}
+
+/// `transformations` after the ones the config applies everywhere.
+fn with_defaults(config: &CloudinaryConfig, transformations: &str) -> String {
+    if config.transformations.is_empty() {
+        transformations.to_string()
+    } else {
+        format!("{},{}", config.transformations, transformations)
+    }
+}
+
+/// `src` with `transformations` applied after any it already has.
+fn transform(src: &str, transformations: &str) -> Option<String> {
+    let mut url = Url::parse(src).ok()?;
+    if url.host_str() != Some(HOST) {
+        return None;
+    }
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    // The cloud, then `image`, then how it is delivered, such as `upload`
+    if segments.len() < 4 || segments[1] != "image" {
+        return None;
+    }
+    let written = segments[3..]
+        .iter()
+        .take_while(|segment| is_transformation(segment))
+        .count();
+    let (before, after) = segments.split_at(3 + written);
+    let path = before
+        .iter()
+        .copied()
+        .chain(std::iter::once(transformations))
+        .chain(after.iter().copied())
+        .collect::<Vec<_>>()
+        .join("/");
+    url.set_path(&path);
+    Some(url.to_string())
+}
+
+/// Whether `segment` is a set of transformations, like `c_scale,f_auto,h_600`.
+fn is_transformation(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment.split(',').all(|parameter| {
+            parameter
+                .split('_')
+                .next()
+                .map(|key| parameter.contains('_') && PARAMETERS.contains(&key))
+                .unwrap_or(false)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD: &str = "https://res.cloudinary.com/dknopoff/image/upload";
+
+    fn url(path: &str) -> String {
+        format!("{}/{}", UPLOAD, path)
+    }
+
+    #[test]
+    fn transform_follows_the_written_transformations() {
+        assert_eq!(
+            transform(
+                &url("c_scale,f_auto,h_600/v1605190430/portfolio/brussels.jpg"),
+                "w_480"
+            ),
+            Some(url(
+                "c_scale,f_auto,h_600/w_480/v1605190430/portfolio/brussels.jpg"
+            ))
+        );
+        assert_eq!(
+            transform(
+                &url("f_auto/e_blur_faces:500/v1/portfolio/IMG_0397.jpg"),
+                "w_480"
+            ),
+            Some(url(
+                "f_auto/e_blur_faces:500/w_480/v1/portfolio/IMG_0397.jpg"
+            ))
+        );
+    }
+
+    #[test]
+    fn transform_goes_before_the_version_and_public_id() {
+        assert_eq!(
+            transform(&url("v1535335229/portfolio/poster.png"), "c_limit,w_960"),
+            Some(url("c_limit,w_960/v1535335229/portfolio/poster.png"))
+        );
+        assert_eq!(
+            transform(&url("portfolio/poster.png"), "c_limit,w_960"),
+            Some(url("c_limit,w_960/portfolio/poster.png"))
+        );
+    }
+
+    #[test]
+    fn transform_leaves_other_urls_alone() {
+        assert_eq!(
+            transform("https://example.com/image/upload/v1/a.jpg", "w_480"),
+            None
+        );
+        assert_eq!(
+            transform(
+                "https://res.cloudinary.com/dknopoff/video/upload/v1/a.mp4",
+                "w_480"
+            ),
+            None
+        );
+        assert_eq!(transform(UPLOAD, "w_480"), None);
+        assert_eq!(transform("/brussels.jpg", "w_480"), None);
+    }
+
+    #[test]
+    fn folders_are_not_transformations() {
+        assert!(is_transformation("c_scale,f_auto,h_600"));
+        assert!(is_transformation("e_blur_faces:500"));
+        assert!(!is_transformation("portfolio"));
+        assert!(!is_transformation("my_photos"));
+        assert!(!is_transformation("v1605190430"));
+        assert!(!is_transformation(""));
+    }
+
+    #[test]
+    fn versions_and_thumbnails_follow_the_config() {
+        let config = CloudinaryConfig {
+            widths: vec![960, 480, 960],
+            transformations: "f_auto,q_auto".to_string(),
+        };
+        let src = url("v1/portfolio/poster.png");
+        assert_eq!(
+            versions(&config, &src),
+            Some(vec![
+                (
+                    url("f_auto,q_auto,c_limit,w_480/v1/portfolio/poster.png"),
+                    480
+                ),
+                (
+                    url("f_auto,q_auto,c_limit,w_960/v1/portfolio/poster.png"),
+                    960
+                ),
+            ])
+        );
+        assert_eq!(
+            thumbnail(&config, &src, 240),
+            Some(url(
+                "f_auto,q_auto,c_fill,g_auto,w_240,h_240/v1/portfolio/poster.png"
+            ))
+        );
+        let off = CloudinaryConfig {
+            widths: Vec::new(),
+            ..config
+        };
+        assert_eq!(versions(&off, &src), None);
+        assert_eq!(thumbnail(&off, &src, 240), None);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::taxonomy::Taxonomy;
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+/// Site-wide settings from `config.yaml`. Every field is optional and
+/// defaults to the values for dustinknopoff.dev.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub title: String,
+    /// Also the tagline in the sidebar.
+    pub description: String,
+    /// Page the sidebar tagline links to, from the site root.
+    pub about: Option<String>,
+    /// Links shown in the sidebar above the index of each taxonomy.
+    pub menu: Vec<MenuLink>,
+    /// Absolute URL the site is served from, without a trailing slash.
+    pub base_url: String,
+    pub author: String,
+    /// Directory of runtime templates that replace the built-in markup.
+    /// Needs the `runtime-templates` feature.
+    pub templates: Option<PathBuf>,
+    pub related: RelatedConfig,
+    /// Size each tag on the tags page by how many posts use it.
+    pub tag_cloud: bool,
+    /// Ways of grouping posts besides tags. See [`Taxonomy`].
+    pub taxonomies: Vec<Taxonomy>,
+    /// Draw a preview image for each post, shared in place of an image of
+    /// its own when it has none.
+    pub cards: bool,
+    pub cloudinary: CloudinaryConfig,
+    /// How a caption can be written under an image, besides as its title.
+    pub captions: Captions,
+}
+
+/// A link in the sidebar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MenuLink {
+    pub name: String,
+    /// From the site root.
+    pub url: String,
+}
+
+/// How related posts are picked for the end of each post.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelatedConfig {
+    /// How many to show. `0` turns them off.
+    pub count: usize,
+    /// Also score posts by how similar their text is, not only by the tags
+    /// they share.
+    pub content: bool,
+}
+
+/// How images hosted on Cloudinary are offered at different sizes. The URLs
+/// are worked out from the one in the post, so nothing is fetched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CloudinaryConfig {
+    /// Widths, in pixels, to offer each image at. Empty leaves the URLs as
+    /// written.
+    pub widths: Vec<u32>,
+    /// Transformations for every width, such as the format and quality.
+    pub transformations: String,
+}
+
+/// Markdown that, directly under a paragraph holding only an image, is
+/// taken as that image's caption.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Captions {
+    /// Only titles, as in `![alt](src "caption")`, are captions.
+    None,
+    /// A quote, as in `> caption`.
+    #[default]
+    Blockquote,
+    /// A paragraph that is all emphasis, as in `*caption*`.
+    Emphasis,
+}
+
+impl Default for CloudinaryConfig {
+    fn default() -> Self {
+        CloudinaryConfig {
+            widths: vec![480, 960, 1440],
+            transformations: String::from("f_auto,q_auto"),
+        }
+    }
+}
+
+impl Default for RelatedConfig {
+    fn default() -> Self {
+        RelatedConfig {
+            count: 3,
+            content: true,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            title: String::from("Dustin Knopoff"),
+            description: String::from("rustacean, cook, and martial arts enthusiast"),
+            about: Some(String::from("/about.html")),
+            menu: vec![
+                MenuLink {
+                    name: String::from("dev"),
+                    url: String::from("/tags/dev.html"),
+                },
+                MenuLink {
+                    name: String::from("design"),
+                    url: String::from("/tags/design.html"),
+                },
+            ],
+            base_url: String::from("https://dustinknopoff.dev"),
+            author: String::from("Dustin Knopoff"),
+            templates: None,
+            related: RelatedConfig::default(),
+            tag_cloud: false,
+            taxonomies: Vec::new(),
+            cards: true,
+            cloudinary: CloudinaryConfig::default(),
+            captions: Captions::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config at `path`, or the defaults when there is none.
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let config: Config = match fs::read_to_string(path) {
+            Ok(source) => serde_yaml::from_str(&source)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path, e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e.into()),
+        };
+        for taxonomy in config.taxonomies.iter() {
+            taxonomy
+                .validate()
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Tags, then every taxonomy the config declares.
+    pub fn all_taxonomies(&self) -> Vec<Taxonomy> {
+        std::iter::once(Taxonomy::tags(self.tag_cloud))
+            .chain(self.taxonomies.iter().cloned())
+            .collect()
+    }
+
+    /// The absolute URL of `path`, relative to the site root.
+    pub fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+}
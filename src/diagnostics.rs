@@ -0,0 +1,193 @@
+use std::{fmt, path::PathBuf};
+
+/// Characters of context shown either side of the error on long lines, such
+/// as those in generated HTML.
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Whether a problem fails the build or is only worth knowing about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with a single file, pointing at where in the file it is.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub message: String,
+    pub severity: Severity,
+    /// One-based line and column within `path`.
+    pub position: Option<(usize, usize)>,
+    /// The offending source line.
+    pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new<P: Into<PathBuf>, M: fmt::Display>(path: P, message: M) -> Self {
+        Diagnostic {
+            path: path.into(),
+            message: message.to_string(),
+            severity: Severity::Error,
+            position: None,
+            snippet: None,
+        }
+    }
+
+    /// Reports the problem without failing the build.
+    pub fn warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+
+    /// Points the diagnostic at byte `offset` of `source`, the full contents
+    /// of the file.
+    pub fn at(mut self, source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = source.get(..offset).unwrap_or(source);
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+        self.position = Some((line, column));
+        self.snippet = source[line_start..].lines().next().map(String::from);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        writeln!(f, "{}: {}", label, self.message)?;
+        match self.position {
+            Some((line, column)) => {
+                writeln!(f, "  --> {}:{}:{}", self.path.display(), line, column)?
+            }
+            None => writeln!(f, "  --> {}", self.path.display())?,
+        }
+        if let (Some((line, column)), Some(snippet)) = (self.position, &self.snippet) {
+            let skip = (column - 1).saturating_sub(SNIPPET_CONTEXT);
+            let snippet = snippet
+                .chars()
+                .skip(skip)
+                .take(SNIPPET_CONTEXT * 2)
+                .collect::<String>();
+            let gutter = " ".repeat(line.to_string().len());
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", line, snippet)?;
+            writeln!(f, "{} | {}^", gutter, " ".repeat(column - 1 - skip))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Every problem found during a build, reported together at the end.
+#[derive(Debug, Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.0.extend(other.0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    /// Whether any of the problems should fail the build.
+    pub fn has_errors(&self) -> bool {
+        self.errors() > 0
+    }
+
+    pub fn errors(&self) -> usize {
+        self.0
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+
+    /// Prints every diagnostic followed by a one line summary.
+    pub fn report(&self) {
+        for diagnostic in self.0.iter() {
+            eprintln!("{}", diagnostic);
+        }
+        if !self.is_empty() {
+            eprintln!("{}", self.summary());
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut files = self.0.iter().map(|d| &d.path).collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        let warnings = self.len() - self.errors();
+        if warnings == 0 {
+            format!("{} error(s) in {} file(s)", self.len(), files.len())
+        } else {
+            format!(
+                "{} error(s) and {} warning(s) in {} file(s)",
+                self.errors(),
+                warnings,
+                files.len()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn at_counts_lines_and_characters() {
+        let source = "title: Crème\ndate: nope\n";
+        let offset = source.find("nope").unwrap();
+        let diagnostic = Diagnostic::new("post.md", "bad date").at(source, offset);
+        assert_eq!(diagnostic.position, Some((2, 7)));
+        assert_eq!(diagnostic.snippet.as_deref(), Some("date: nope"));
+
+        // Columns count characters, not bytes
+        let offset = source.find('m').unwrap();
+        let diagnostic = Diagnostic::new("post.md", "accent").at(source, offset);
+        assert_eq!(diagnostic.position, Some((1, 11)));
+    }
+
+    #[test]
+    fn at_clamps_to_the_end_of_the_file() {
+        let diagnostic = Diagnostic::new("post.md", "at the end").at("one\ntwo", 100);
+        assert_eq!(diagnostic.position, Some((2, 4)));
+        assert_eq!(diagnostic.snippet.as_deref(), Some("two"));
+        let diagnostic = Diagnostic::new("post.md", "empty").at("", 0);
+        assert_eq!(diagnostic.position, Some((1, 1)));
+        assert_eq!(diagnostic.snippet, None);
+    }
+
+    #[test]
+    fn summary_counts_errors_warnings_and_files() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.push(Diagnostic::new("a.md", "one"));
+        diagnostics.push(Diagnostic::new("a.md", "two").warning());
+        diagnostics.push(Diagnostic::new("b.md", "three").warning());
+        assert!(diagnostics.has_errors());
+        assert_eq!(
+            diagnostics.summary(),
+            "1 error(s) and 2 warning(s) in 2 file(s)"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Grids of images that each open full size, for posts that are mostly
+//! pictures. A post lists them in its frontmatter,
+//!
+//! ```yaml
+//! layout: gallery
+//! gallery:
+//!   - src: https://res.cloudinary.com/dknopoff/image/upload/v1/portfolio/poster.png
+//!     alt: A poster of overlapping letters
+//!     caption: For Typography 1
+//! ```
+//!
+//! which the `gallery` layout puts before the write-up and other layouts
+//! after it, or one per line as Markdown images in a fenced `gallery` block,
+//! shown where the block is:
+//!
+//! ````markdown
+//! ```gallery
+//! ![A poster of overlapping letters](/poster.png "For Typography 1")
+//! ```
+//! ````
+use crate::{cloudinary, config::CloudinaryConfig, images, retrieve::Post};
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Info string of the fenced blocks that are galleries.
+pub const FENCE: &str = "gallery";
+
+/// Width and height, in pixels, of the square thumbnails.
+pub const THUMBNAIL: u32 = 480;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GalleryImage {
+    pub src: String,
+    /// `None` when there is none. An empty `alt` in the frontmatter marks an
+    /// image as decorative.
+    #[serde(default)]
+    pub alt: Option<String>,
+    #[serde(default)]
+    pub caption: Option<String>,
+}
+
+impl GalleryImage {
+    /// Where the square thumbnail is. Images that can't be cropped are
+    /// shown whole, squeezed in by the stylesheet.
+    pub fn thumbnail(&self, cloudinary: Option<&CloudinaryConfig>) -> String {
+        cloudinary
+            .and_then(|config| cloudinary::thumbnail(config, &self.src, THUMBNAIL))
+            .or_else(|| images::thumbnail_url(&self.src))
+            .unwrap_or_else(|| self.src.clone())
+    }
+
+    /// Where the image is at its largest, for the thumbnail to link to.
+    pub fn full(&self, cloudinary: Option<&CloudinaryConfig>) -> String {
+        cloudinary
+            .and_then(|config| cloudinary::versions(config, &self.src))
+            .and_then(|versions| versions.last().map(|(url, _)| url.clone()))
+            .unwrap_or_else(|| self.src.clone())
+    }
+}
+
+/// The images in the body of a fenced `gallery` block: every Markdown image
+/// in it, with its title as the caption.
+pub fn parse_block(text: &str) -> Vec<GalleryImage> {
+    let mut images = Vec::new();
+    let mut alt: Option<String> = None;
+    for event in Parser::new(text) {
+        match event {
+            Event::Start(Tag::Image(..)) => alt = Some(String::new()),
+            Event::Text(ref text) | Event::Code(ref text) => {
+                if let Some(ref mut alt) = alt {
+                    alt.push_str(text);
+                }
+            }
+            Event::End(Tag::Image(_, src, title)) => images.push(GalleryImage {
+                src: src.to_string(),
+                alt: alt.take().filter(|alt| !alt.trim().is_empty()),
+                caption: Some(title.to_string()).filter(|title| !title.is_empty()),
+            }),
+            _ => {}
+        }
+    }
+    images
+}
+
+/// Every image in `post`'s galleries: those in its frontmatter, then those
+/// in each `gallery` block, with relative sources resolved.
+pub fn images(post: &Post) -> Vec<GalleryImage> {
+    let mut images = post.frontmatter.gallery.clone();
+    let mut in_gallery = false;
+    for event in Parser::new(&post.content) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang))) => {
+                in_gallery = lang.as_ref() == FENCE;
+            }
+            Event::Text(ref text) if in_gallery => {
+                images.extend(parse_block(text).into_iter().map(|image| GalleryImage {
+                    src: post.resolve(&image.src),
+                    ..image
+                }))
+            }
+            Event::End(Tag::CodeBlock(_)) => in_gallery = false,
+            _ => {}
+        }
+    }
+    images
+}
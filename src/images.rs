@@ -0,0 +1,531 @@
+//! Responsive versions of the images posts show from this site: copies
+//! scaled down for narrower screens, and WebP and AVIF encodings for
+//! browsers that support them.
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics},
+    gallery::{self, THUMBNAIL},
+    output::Output,
+    persist::{file_hash, BuildCache},
+    retrieve::{slugify, Post},
+};
+use image::{
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageFormat, ImageReader,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use pulldown_cmark::{Event, Parser, Tag};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+/// Widths, in pixels, of the scaled-down copies. Images are never scaled up.
+const WIDTHS: &[u32] = &[480, 960, 1440];
+
+/// How wide images are shown, matching the content column in `style.css`.
+pub const SIZES: &str = "(max-width: 480px) 100vw, 74vw";
+
+const JPEG_QUALITY: u8 = 80;
+/// From 0 to 100, as for JPEG.
+const WEBP_QUALITY: f32 = 80.0;
+const AVIF_QUALITY: u8 = 70;
+/// From 1, smallest files, to 10, fastest to encode.
+const AVIF_SPEED: u8 = 8;
+
+/// The responsive versions of each image in a post, by the `src` it was
+/// written with.
+pub type Pictures = BTreeMap<String, Picture>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    /// Size of the original, so the page can leave room for it.
+    pub width: u32,
+    pub height: u32,
+    /// Hash of the original's contents.
+    pub hash: String,
+    /// Alternatives for browsers to pick from, best first. The last is in
+    /// the original's format, for the `<img>` itself.
+    pub sources: Vec<Source>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub mime: &'static str,
+    /// Each version's URL and width, narrowest first.
+    pub versions: Vec<(String, u32)>,
+}
+
+impl Source {
+    pub fn srcset(&self) -> String {
+        srcset(&self.versions)
+    }
+}
+
+/// A `srcset` attribute listing each URL with its width.
+pub fn srcset(versions: &[(String, u32)]) -> String {
+    versions
+        .iter()
+        .map(|(url, width)| format!("{} {}w", url, width))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Characters that would split a URL in a `srcset`, or aren't allowed in
+/// one. Anything else is left as written, so escapes already in a `src`
+/// aren't escaped twice.
+const SRCSET_ESCAPED: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b',')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`');
+
+/// Formats originals can be in, by extension, and the formats each gets
+/// versions in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Avif,
+    Webp,
+    Jpeg,
+    Png,
+}
+
+impl Encoding {
+    fn of(file: &Path) -> Option<Self> {
+        let extension = file.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "jpg" | "jpeg" => Some(Encoding::Jpeg),
+            "png" => Some(Encoding::Png),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Avif => "avif",
+            Encoding::Webp => "webp",
+            Encoding::Jpeg => "jpg",
+            Encoding::Png => "png",
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Encoding::Avif => "image/avif",
+            Encoding::Webp => "image/webp",
+            Encoding::Jpeg => "image/jpeg",
+            Encoding::Png => "image/png",
+        }
+    }
+
+    fn encode(self, image: &DynamicImage) -> Result<Vec<u8>, anyhow::Error> {
+        let mut bytes = Vec::new();
+        // Encoders take 8-bit RGB, with alpha only where there is any
+        let image = if image.color().has_alpha() {
+            DynamicImage::ImageRgba8(image.to_rgba8())
+        } else {
+            DynamicImage::ImageRgb8(image.to_rgb8())
+        };
+        match self {
+            Encoding::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut bytes,
+                AVIF_SPEED,
+                AVIF_QUALITY,
+            ))?,
+            Encoding::Webp => {
+                let (width, height) = (image.width(), image.height());
+                let encoder = if image.color().has_alpha() {
+                    webp::Encoder::from_rgba(image.as_bytes(), width, height)
+                } else {
+                    webp::Encoder::from_rgb(image.as_bytes(), width, height)
+                };
+                bytes.extend_from_slice(&encoder.encode(WEBP_QUALITY));
+            }
+            Encoding::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))?,
+            Encoding::Png => image.write_with_encoder(PngEncoder::new(&mut bytes))?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Writes responsive versions of every local JPEG and PNG `post` shows,
+/// and thumbnails of those in its galleries, returning what was made of the
+/// first. Versions already in `cache` are copied rather than encoded again.
+/// Images that can't be read or encoded are left as they are and reported.
+pub fn write_pictures(
+    resource_dir: &str,
+    post: &Post,
+    cache: &mut BuildCache,
+    out: &mut Output,
+) -> (Pictures, Diagnostics) {
+    let mut diagnostics = Diagnostics::default();
+    for image in gallery::images(post) {
+        let (file, url) = match (
+            source_file(resource_dir, post, &image.src),
+            thumbnail_url(&image.src),
+        ) {
+            (Some(file), Some(url)) if file.is_file() => (file, url),
+            _ => continue,
+        };
+        if let Err(e) = write_thumbnail(&file, &url, cache, out) {
+            diagnostics.push(Diagnostic::new(
+                &post.filename.0,
+                format!("couldn't make a thumbnail of `{}`: {}", image.src, e),
+            ));
+        }
+    }
+    let mut pictures = Pictures::new();
+    for src in images(&post.content) {
+        let src = post.resolve(&src);
+        if pictures.contains_key(&src) {
+            continue;
+        }
+        let file = match source_file(resource_dir, post, &src) {
+            Some(file) if file.is_file() => file,
+            // Missing files are left for the link checker to complain about
+            _ => continue,
+        };
+        let original = match Encoding::of(&file) {
+            Some(original) => original,
+            None => continue,
+        };
+        match picture(&src, &file, original, cache, out) {
+            Ok(picture) => {
+                pictures.insert(src, picture);
+            }
+            Err(e) => diagnostics.push(Diagnostic::new(
+                &post.filename.0,
+                format!("couldn't make responsive versions of `{}`: {}", src, e),
+            )),
+        }
+    }
+    (pictures, diagnostics)
+}
+
+fn picture(
+    src: &str,
+    file: &Path,
+    original: Encoding,
+    cache: &mut BuildCache,
+    out: &mut Output,
+) -> Result<Picture, anyhow::Error> {
+    let contents = fs::read(file)?;
+    let hash = file_hash(&contents);
+    let format = match original {
+        Encoding::Jpeg => ImageFormat::Jpeg,
+        _ => ImageFormat::Png,
+    };
+    let (width, height) =
+        ImageReader::with_format(Cursor::new(&contents), format).into_dimensions()?;
+    // Spaces would split the URL in a `srcset`
+    let stem = file
+        .file_stem()
+        .map(|stem| slugify(&stem.to_string_lossy()))
+        .unwrap_or_default();
+    let mut widths = WIDTHS
+        .iter()
+        .copied()
+        .filter(|w| *w < width)
+        .collect::<Vec<_>>();
+    widths.push(width);
+    // Decoding is slow, so only happens when a version needs encoding
+    let mut decoded = None;
+    let mut sources = Vec::new();
+    for encoding in [Encoding::Avif, Encoding::Webp, original].iter().copied() {
+        let mut versions = Vec::new();
+        for w in widths.iter().copied() {
+            if encoding == original && w == width {
+                let url = utf8_percent_encode(src, SRCSET_ESCAPED).to_string();
+                versions.push((url, w, None));
+                continue;
+            }
+            let key = format!("{}-{}", hash, w);
+            let bytes = match cache.get_file(&key, encoding.extension()) {
+                Some(bytes) => bytes,
+                None => {
+                    if decoded.is_none() {
+                        decoded = Some(image::load_from_memory_with_format(&contents, format)?);
+                    }
+                    let image = decoded.as_ref().unwrap();
+                    let bytes = if w == width {
+                        encoding.encode(image)?
+                    } else {
+                        let h = ((height as u64 * w as u64) / width as u64).max(1) as u32;
+                        encoding.encode(&image.resize_exact(w, h, FilterType::CatmullRom))?
+                    };
+                    // A dry run leaves the cache as it was, too
+                    if !out.is_dry_run() {
+                        cache.insert_file(&key, encoding.extension(), &bytes)?;
+                    }
+                    bytes
+                }
+            };
+            let url = format!(
+                "/images/{}-{}-{}.{}",
+                stem,
+                &hash[..8],
+                w,
+                encoding.extension()
+            );
+            versions.push((url, w, Some(bytes)));
+        }
+        // A version bigger than the original, as a small or already well
+        // compressed image can come out, would only make the page slower
+        // for browsers that pick it
+        let largest = versions
+            .last()
+            .and_then(|(_, _, bytes)| bytes.as_ref())
+            .map(Vec::len)
+            .unwrap_or(0);
+        if encoding != original && largest >= contents.len() {
+            continue;
+        }
+        for (url, _, bytes) in versions.iter() {
+            if let Some(bytes) = bytes {
+                out.write(format!("public{}", url), bytes)?;
+            }
+        }
+        sources.push(Source {
+            mime: encoding.mime(),
+            versions: versions.into_iter().map(|(url, w, _)| (url, w)).collect(),
+        });
+    }
+    Ok(Picture {
+        width,
+        height,
+        hash,
+        sources,
+    })
+}
+
+/// Where the square thumbnail of the local image at `src` is written, for
+/// JPEGs and PNGs.
+pub fn thumbnail_url(src: &str) -> Option<String> {
+    let path = PathBuf::from(site_path(src)?);
+    let encoding = Encoding::of(&path)?;
+    let name = slugify(&path.with_extension("").to_string_lossy());
+    Some(format!(
+        "/images/thumbnails/{}.{}",
+        name,
+        encoding.extension()
+    ))
+}
+
+fn write_thumbnail(
+    file: &Path,
+    url: &str,
+    cache: &mut BuildCache,
+    out: &mut Output,
+) -> Result<(), anyhow::Error> {
+    let contents = fs::read(file)?;
+    let key = format!("{}-thumbnail", file_hash(&contents));
+    let encoding = Encoding::of(file).unwrap_or(Encoding::Png);
+    let bytes = match cache.get_file(&key, encoding.extension()) {
+        Some(bytes) => bytes,
+        None => {
+            let image = image::load_from_memory(&contents)?;
+            let bytes = encoding.encode(&image.resize_to_fill(
+                THUMBNAIL,
+                THUMBNAIL,
+                FilterType::CatmullRom,
+            ))?;
+            if !out.is_dry_run() {
+                cache.insert_file(&key, encoding.extension(), &bytes)?;
+            }
+            bytes
+        }
+    };
+    out.write(format!("public{}", url), &bytes)
+}
+
+/// The `src` of every image in `markdown`, as written.
+fn images(markdown: &str) -> Vec<String> {
+    Parser::new(markdown)
+        .filter_map(|event| match event {
+            Event::Start(Tag::Image(_, src, _)) => Some(src.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The file that `src`, resolved against `post`, loads when it is a path on
+/// this site rather than a URL elsewhere: one in `post`'s bundle, or else
+/// under `resource_dir`.
+fn source_file(resource_dir: &str, post: &Post, src: &str) -> Option<PathBuf> {
+    let path = site_path(src)?;
+    if let Some(dir) = post.filename.bundle_dir() {
+        if let Some(rest) = path.strip_prefix(post.base().as_str()) {
+            return Some(dir.join(rest));
+        }
+    }
+    Some(PathBuf::from(resource_dir).join(path.trim_start_matches('/')))
+}
+
+/// The decoded path of `src`, when it is a path from the site root.
+fn site_path(src: &str) -> Option<String> {
+    if src.starts_with("//") || !src.starts_with('/') {
+        return None;
+    }
+    let path = src.split(&['?', '#'][..]).next()?;
+    Some(percent_decode_str(path).decode_utf8().ok()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retrieve::{FrontMatter, SourcePath};
+    use chrono::DateTime;
+    use image::{Rgb, RgbImage};
+
+    /// An empty directory of the test's own.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portfolio-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn post(content: &str) -> Post {
+        Post {
+            frontmatter: FrontMatter::default(),
+            content: content.to_string(),
+            filename: SourcePath(PathBuf::from("content/rust/hello.md")),
+            hash: String::new(),
+            date: DateTime::parse_from_rfc3339("2021-03-04T00:00:00-05:00").unwrap(),
+            tag_names: BTreeMap::new(),
+            written_tags: Vec::new(),
+            linked: BTreeMap::new(),
+        }
+    }
+
+    /// A `width` by `height` PNG of noise, which compresses badly enough
+    /// that every other format comes out smaller.
+    fn noise(path: &Path, width: u32, height: u32) {
+        let mut seed = 1u32;
+        let image = RgbImage::from_fn(width, height, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let [r, g, b, _] = seed.to_be_bytes();
+            Rgb([r, g, b])
+        });
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(path).unwrap();
+    }
+
+    #[test]
+    fn srcsets_list_each_width() {
+        let versions = vec![
+            (String::from("/images/a-480.webp"), 480),
+            (String::from("/images/a-960.webp"), 960),
+        ];
+        assert_eq!(
+            srcset(&versions),
+            "/images/a-480.webp 480w, /images/a-960.webp 960w"
+        );
+    }
+
+    #[test]
+    fn only_paths_from_the_site_root_are_local() {
+        assert_eq!(
+            site_path("/images/my%20cat.png?v=2#top").as_deref(),
+            Some("/images/my cat.png")
+        );
+        assert_eq!(site_path("//cdn.example.com/cat.png"), None);
+        assert_eq!(site_path("https://example.com/cat.png"), None);
+        assert_eq!(site_path("cat.png"), None);
+        assert_eq!(
+            thumbnail_url("/images/My Cat.JPG").as_deref(),
+            Some("/images/thumbnails/images-my-cat.jpg")
+        );
+        assert_eq!(thumbnail_url("/images/cat.gif"), None);
+    }
+
+    #[test]
+    fn pictures_offer_smaller_widths_and_formats() {
+        let dir = scratch("images-pictures");
+        let resources = dir.join("resources");
+        noise(&resources.join("images/my cat.png"), 520, 120);
+        let mut cache = BuildCache::open(dir.join("cache"));
+        let mut out = Output::new(dir.join("public"), dir.join("cache"), false);
+        let (pictures, problems) = write_pictures(
+            resources.to_str().unwrap(),
+            &post("![a cat](/images/my%20cat.png) ![gone](/images/gone.png)"),
+            &mut cache,
+            &mut out,
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+        assert_eq!(pictures.len(), 1);
+        let picture = &pictures["/images/my%20cat.png"];
+        assert_eq!((picture.width, picture.height), (520, 120));
+        let mimes = picture.sources.iter().map(|s| s.mime).collect::<Vec<_>>();
+        assert_eq!(mimes, vec!["image/avif", "image/webp", "image/png"]);
+        let prefix = format!("/images/my-cat-{}", &picture.hash[..8]);
+        assert_eq!(
+            picture.sources[1].versions,
+            vec![
+                (format!("{}-480.webp", prefix), 480),
+                (format!("{}-520.webp", prefix), 520),
+            ]
+        );
+        // The original at full width is served as it is
+        assert_eq!(
+            picture.sources[2].versions,
+            vec![
+                (format!("{}-480.png", prefix), 480),
+                (String::from("/images/my%20cat.png"), 520),
+            ]
+        );
+        assert!(out
+            .produced()
+            .contains(&PathBuf::from(format!("public{}-480.avif", prefix))));
+        assert!(dir
+            .join("cache")
+            .join(format!("{}-480.avif", picture.hash))
+            .exists());
+    }
+
+    #[test]
+    fn dry_runs_leave_the_cache_alone() {
+        let dir = scratch("images-dry-run");
+        let resources = dir.join("resources");
+        noise(&resources.join("images/cat.png"), 100, 100);
+        let mut cache = BuildCache::open(dir.join("cache"));
+        let mut out = Output::new(dir.join("public"), dir.join("cache"), true);
+        let (pictures, _) = write_pictures(
+            resources.to_str().unwrap(),
+            &post("![a cat](/images/cat.png)"),
+            &mut cache,
+            &mut out,
+        );
+        assert_eq!(pictures.len(), 1);
+        assert!(!out.produced().is_empty());
+        assert!(!dir.join("cache").exists());
+    }
+
+    #[test]
+    fn unreadable_images_are_reported() {
+        let dir = scratch("images-broken");
+        let resources = dir.join("resources");
+        fs::create_dir_all(resources.join("images")).unwrap();
+        fs::write(resources.join("images/broken.png"), b"not a png").unwrap();
+        let mut cache = BuildCache::open(dir.join("cache"));
+        let mut out = Output::new(dir.join("public"), dir.join("cache"), true);
+        let (pictures, problems) = write_pictures(
+            resources.to_str().unwrap(),
+            &post("![broken](/images/broken.png)"),
+            &mut cache,
+            &mut out,
+        );
+        assert!(pictures.is_empty());
+        let messages = problems.iter().map(|d| &d.message).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 1);
+        assert!(
+            messages[0].starts_with("couldn't make responsive versions of `/images/broken.png`")
+        );
+    }
+}
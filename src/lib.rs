@@ -0,0 +1,361 @@
+//! Static site generator for dustinknopoff.dev.
+//!
+//! The `portfolio-rs` binary builds the site with the built-in theme. To
+//! change the markup without patching this crate, implement
+//! [`theme::Theme`] and build with [`Pipeline::with_theme`]. To change it
+//! without compiling anything, build with the `runtime-templates` feature and
+//! set `templates` in `config.yaml`; see `src/runtime.rs` for what templates
+//! can use.
+pub(crate) mod cache;
+pub(crate) mod cards;
+pub(crate) mod check;
+pub(crate) mod cloudinary;
+pub mod config;
+pub(crate) mod diagnostics;
+pub mod gallery;
+pub(crate) mod images;
+pub(crate) mod links;
+pub mod meta;
+pub mod navigation;
+pub(crate) mod output;
+pub(crate) mod persist;
+pub(crate) mod related;
+pub(crate) mod retrieve;
+#[cfg(feature = "runtime-templates")]
+pub(crate) mod runtime;
+pub(crate) mod structured;
+pub mod tags;
+pub mod taxonomy;
+pub mod templates;
+pub mod theme;
+use cache::PostsDatabase;
+use cards::write_cards;
+use check::check_posts;
+use config::Config;
+use diagnostics::Diagnostics;
+use images::write_pictures;
+use links::check_links;
+use output::{Change, Output};
+use persist::BuildCache;
+pub use persist::Rendered;
+use retrieve::{copy_bundle, copy_resources, find_content};
+pub use retrieve::{FrontMatter, Kind, Post, PublicPath, SourcePath};
+use std::sync::Arc;
+use structured::check_structured_data;
+use tags::Tags;
+use templates::{
+    layout::index,
+    pages::{write_series_to_file, write_taxonomy_to_file},
+};
+use theme::{Templates, Theme};
+
+// TODO:
+// - Search?
+// - Make config for Stuff?
+// - Watch for changes?
+
+pub struct Pipeline {
+    output_dir: &'static str,
+    resource_dir: &'static str,
+    content_dir: &'static str,
+    cache_dir: &'static str,
+    config: Arc<Config>,
+    tags: Tags,
+    templates: Arc<Templates>,
+    #[cfg(feature = "runtime-templates")]
+    runtime: Option<runtime::Engine>,
+    db: PostsDatabase,
+    out: Output,
+    keep_going: bool,
+}
+
+impl Pipeline {
+    pub fn new(
+        output_dir: &'static str,
+        resource_dir: &'static str,
+        content_dir: &'static str,
+        cache_dir: &'static str,
+        options: &Options,
+    ) -> Self {
+        let config = Arc::new(Config::default());
+        let templates = Arc::new(Templates::default());
+        Pipeline {
+            output_dir,
+            resource_dir,
+            content_dir,
+            cache_dir,
+            db: PostsDatabase::new(templates.clone(), config.clone()),
+            config,
+            tags: Tags::default(),
+            templates,
+            #[cfg(feature = "runtime-templates")]
+            runtime: None,
+            out: Output::new(output_dir, cache_dir, options.dry_run || options.check),
+            keep_going: options.keep_going,
+        }
+    }
+
+    /// Renders with `theme` and any layouts it registers instead of the
+    /// built-in theme.
+    pub fn with_theme<T: Theme + 'static>(mut self, theme: T) -> Self {
+        self.templates = Arc::new(Templates::new(theme));
+        self.db = PostsDatabase::new(self.templates.clone(), self.config.clone());
+        self
+    }
+
+    /// Builds with the site settings in `config`.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = Arc::new(config);
+        self.db = PostsDatabase::new(self.templates.clone(), self.config.clone());
+        self
+    }
+
+    /// Files tags using the names, descriptions and aliases in `tags`.
+    pub fn with_tags(mut self, tags: Tags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Every layout name posts may use.
+    fn layout_names(&self) -> Vec<&str> {
+        let names = self.templates.layouts().names();
+        #[cfg(feature = "runtime-templates")]
+        let names = names.chain(self.runtime.iter().flat_map(runtime::Engine::layouts));
+        let mut names = names.collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Loads every content file into the database, returning the ones that
+    /// parsed and the errors for the rest.
+    fn load(
+        &mut self,
+        cache: &mut BuildCache,
+    ) -> Result<(Vec<SourcePath>, Diagnostics), anyhow::Error> {
+        #[cfg(feature = "runtime-templates")]
+        {
+            self.runtime = runtime::Engine::from_config(&self.config)?;
+        }
+        #[cfg(not(feature = "runtime-templates"))]
+        if let Some(ref dir) = self.config.templates {
+            anyhow::bail!(
+                "Templates in {} need a build with `--features runtime-templates`",
+                dir.display()
+            );
+        }
+        let (files, mut diagnostics) = find_content(self.content_dir)?;
+        log::debug!("Found {} markdown files in content/", files.len());
+        let (loaded, problems) = self.db.add_posts(&files, cache, &self.tags);
+        diagnostics.extend(problems);
+        log::debug!("imported files to salsa db");
+        Ok((loaded, diagnostics))
+    }
+
+    /// Renders every page and resource into `self.out`, returning the
+    /// bundle files, images and cards that couldn't be made.
+    fn render(
+        &mut self,
+        files: &[SourcePath],
+        cache: &mut BuildCache,
+    ) -> Result<Diagnostics, anyhow::Error> {
+        let mut diagnostics = Diagnostics::default();
+        copy_resources(self.resource_dir, &mut self.out)?;
+        log::debug!("copied resources in to public/");
+        for path in files.iter() {
+            let post = self.db.post(path);
+            diagnostics.extend(copy_bundle(&post, &mut self.out));
+            let (pictures, problems) =
+                write_pictures(self.resource_dir, &post, cache, &mut self.out);
+            diagnostics.extend(problems);
+            self.db.add_pictures(path, pictures, cache);
+        }
+        log::debug!("Copied bundles and made responsive versions of local images");
+        // Pages are rendered but kept out of the feed, tags and listings
+        let (posts, _pages) = self.db.partition_pages(files);
+        self.db
+            .rss_to_file(self.db.generate_rss(&posts)?, &mut self.out)?;
+        log::debug!("Generated and wrote RSS.");
+        let taxonomies = self
+            .config
+            .all_taxonomies()
+            .into_iter()
+            .map(|taxonomy| {
+                let terms = self.db.get_terms(&taxonomy, &posts);
+                let names = self.db.term_names(&taxonomy, &self.tags, &posts);
+                (taxonomy, terms, names)
+            })
+            .collect::<Vec<_>>();
+        for (taxonomy, terms, names) in taxonomies.iter() {
+            self.db
+                .term_feeds_to_file(taxonomy, names, terms, &mut self.out)?;
+        }
+        log::debug!("Wrote a feed for each tag and term");
+        self.db.write_aliases(files, &mut self.out)?;
+        log::debug!("Wrote redirects from old URLs");
+        if self.config.cards {
+            let (drawn, problems) =
+                write_cards(&self.db, self.resource_dir, &posts, cache, &mut self.out);
+            diagnostics.extend(problems);
+            for path in drawn.iter() {
+                self.db.add_card(path);
+            }
+            log::debug!("Drew preview cards");
+        }
+        #[cfg(feature = "runtime-templates")]
+        if let Some(ref engine) = self.runtime {
+            engine.render_site(
+                &self.db,
+                &self.templates,
+                &self.tags,
+                files,
+                &posts,
+                &mut self.out,
+            )?;
+            log::debug!("Rendered posts, pages and listings from templates");
+            return Ok(diagnostics);
+        }
+        self.db.write_posts_to_file(files, &mut self.out)?;
+        log::debug!("Generated and wrote posts and pages to html files");
+        let theme = self.templates.theme();
+        for (taxonomy, terms, names) in taxonomies.iter() {
+            write_taxonomy_to_file(theme, &self.config, taxonomy, names, terms, &mut self.out)?;
+        }
+        log::debug!("Created tag and taxonomy pages");
+        write_series_to_file(
+            theme,
+            &self.config,
+            &self.db.get_series(&posts),
+            &mut self.out,
+        )?;
+        log::debug!("Created series pages");
+        let markup = index(theme, &self.config, &self.db.five_most_recent(&posts));
+        log::debug!("Retrieved top 5 posts for index building");
+        self.out
+            .write("public/index.html", markup.into_string().as_bytes())?;
+        log::debug!("Created the index.html");
+        Ok(diagnostics)
+    }
+
+    /// Runs `check` or `build`, whichever `options` asked for.
+    pub fn run(self, options: &Options) -> Result<(), anyhow::Error> {
+        if options.check {
+            self.check()
+        } else {
+            self.build()
+        }
+    }
+
+    pub fn build(mut self) -> Result<(), anyhow::Error> {
+        log::debug!("Building into {}", self.output_dir);
+        let mut cache = BuildCache::open(self.cache_dir);
+        let (files, diagnostics) = self.load(&mut cache)?;
+        diagnostics.report();
+        if diagnostics.has_errors() {
+            if !self.keep_going {
+                anyhow::bail!("{}", diagnostics.summary());
+            }
+            log::warn!("Skipping {} file(s) with errors", diagnostics.errors());
+        }
+        let mut problems = self.render(&files, &mut cache)?;
+        problems.extend(check_links(
+            self.out.pages(),
+            self.out.produced(),
+            &self.db.unpublished_links(&files),
+        ));
+        problems.extend(self.out.collisions());
+        log::debug!("Checked internal links and colliding outputs");
+        // Before anything is written, so a failing build publishes nothing
+        problems.report();
+        if problems.has_errors() && !self.keep_going {
+            anyhow::bail!("{}", problems.summary());
+        }
+        let dry_run = self.out.is_dry_run();
+        let changes = self.out.finish()?;
+        if dry_run {
+            for (change, path) in changes.iter() {
+                println!("{:?}\t{}", change, path.display());
+            }
+            println!("{} file(s) would change", changes.len());
+            return Ok(());
+        }
+        log::debug!(
+            "Wrote {} new, {} updated and removed {} stale file(s)",
+            changes
+                .iter()
+                .filter(|(c, _)| *c == Change::Created)
+                .count(),
+            changes
+                .iter()
+                .filter(|(c, _)| *c == Change::Updated)
+                .count(),
+            changes
+                .iter()
+                .filter(|(c, _)| *c == Change::Removed)
+                .count(),
+        );
+        self.db.persist(&files, &mut cache)?;
+        let pruned = cache.prune()?;
+        log::debug!(
+            "Saved rendered posts to cache, pruned {} stale entries",
+            pruned
+        );
+        Ok(())
+    }
+
+    /// Runs the whole pipeline without writing anything, then validates the
+    /// content against what would have been generated.
+    pub fn check(mut self) -> Result<(), anyhow::Error> {
+        let mut cache = BuildCache::open(self.cache_dir);
+        let (files, mut diagnostics) = self.load(&mut cache)?;
+        diagnostics.extend(self.render(&files, &mut cache)?);
+        diagnostics.extend(check_posts(
+            &self.db,
+            &self.layout_names(),
+            &files,
+            self.out.produced(),
+        ));
+        diagnostics.extend(check_links(
+            self.out.pages(),
+            self.out.produced(),
+            &self.db.unpublished_links(&files),
+        ));
+        diagnostics.extend(self.out.collisions());
+        diagnostics.extend(check_structured_data(self.out.pages()));
+        if diagnostics.is_empty() {
+            println!("Checked {} file(s), no problems found", files.len());
+            return Ok(());
+        }
+        diagnostics.report();
+        if diagnostics.has_errors() {
+            anyhow::bail!("{}", diagnostics.summary());
+        }
+        Ok(())
+    }
+}
+
+/// Command line flags.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// `check`: validate content and exit non-zero on problems, writing nothing.
+    pub check: bool,
+    /// Report which output files would change without touching them.
+    pub dry_run: bool,
+    /// Build everything that parses instead of failing on content errors.
+    pub keep_going: bool,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, anyhow::Error> {
+        let mut options = Options::default();
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                "check" => options.check = true,
+                "--dry-run" => options.dry_run = true,
+                "--keep-going" => options.keep_going = true,
+                other => anyhow::bail!("Unknown argument `{}`", other),
+            }
+        }
+        Ok(options)
+    }
+}
@@ -0,0 +1,440 @@
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics},
+    retrieve::{Post, PublicPath, SourcePath},
+};
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{Event, Parser, Tag};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+use url::{Position, Url};
+
+/// Stand-in origin used to resolve links; anything that resolves elsewhere is
+/// external to the site.
+const SITE: &str = "https://site.invalid/";
+
+/// Resolves a link found on the page at `from` to the output file it would
+/// load and the fragment it targets, or `None` when it leaves the site.
+pub fn resolve(from: &PublicPath, dest: &str) -> Option<(PathBuf, Option<String>)> {
+    if dest.is_empty() {
+        return None;
+    }
+    let site = Url::parse(SITE).ok()?;
+    let url = site.join(from.0.to_str()?).ok()?.join(dest).ok()?;
+    if url.origin() != site.origin() {
+        return None;
+    }
+    let path = percent_decode_str(url.path()).decode_utf8().ok()?;
+    let mut target = PathBuf::from("public");
+    target.push(path.trim_start_matches('/'));
+    if path.ends_with('/') {
+        target.push("index.html");
+    }
+    let fragment = url
+        .fragment()
+        .and_then(|f| percent_decode_str(f).decode_utf8().ok())
+        .map(String::from);
+    Some((target, fragment))
+}
+
+/// `dest` as a path from the site root, with any query and fragment, when
+/// it is relative to the directory `base`, such as `shell.png` or
+/// `../about.html`. Links to other sites, to the site root and to fragments
+/// of the same page give `None`.
+pub fn absolute(base: &str, dest: &str) -> Option<String> {
+    if !is_relative(dest) {
+        return None;
+    }
+    let url = Url::parse(SITE).ok()?.join(base).ok()?.join(dest).ok()?;
+    Some(url[Position::BeforePath..].to_string())
+}
+
+/// Whether `dest` is a path relative to the page it is on, rather than a
+/// URL with a scheme, like `https:` or `mailto:`, a path from the site root
+/// or a fragment of the same page.
+pub fn is_relative(dest: &str) -> bool {
+    !dest.is_empty()
+        && !dest.starts_with(&['/', '#', '?'][..])
+        && matches!(
+            Url::parse(dest),
+            Err(url::ParseError::RelativeUrlWithoutBase)
+        )
+}
+
+/// Errors for each link in `post` to a Markdown file that isn't one of the
+/// posts and pages in `sources`.
+pub fn check_sources(post: &Post, sources: &[SourcePath]) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let mut source = None;
+    for (event, range) in Parser::new(&post.content).into_offset_iter() {
+        let dest = match event {
+            Event::Start(Tag::Link(_, ref dest, _)) => dest,
+            _ => continue,
+        };
+        let target = match post.linked_source(dest) {
+            Some((target, _)) if !sources.contains(&target) => target,
+            _ => continue,
+        };
+        // Only read again to say where the link is
+        let text =
+            source.get_or_insert_with(|| fs::read_to_string(&post.filename.0).unwrap_or_default());
+        let body_start = text.find(post.content.as_str()).unwrap_or(0);
+        diagnostics.push(
+            Diagnostic::new(
+                post.filename.0.clone(),
+                format!(
+                    "`{}` links to {}, which is not a post or page",
+                    dest,
+                    target.0.display()
+                ),
+            )
+            .at(text, body_start + range.start),
+        );
+    }
+    diagnostics
+}
+
+/// Checks every internal `href` and `src` in the generated `pages` resolves to
+/// a `produced` file and, for anchors, to an element ID or a named `<a>` on
+/// that page. Links to `reported` files, made from links to Markdown files
+/// that `check_sources` already reported, are skipped.
+pub fn check_links(
+    pages: &BTreeMap<PathBuf, String>,
+    produced: &BTreeSet<PathBuf>,
+    reported: &BTreeSet<PathBuf>,
+) -> Diagnostics {
+    let ids = pages
+        .iter()
+        .map(|(path, html)| {
+            let ids = attributes(html)
+                .filter(|(element, name, _, _)| {
+                    *name == "id" || (*name == "name" && *element == "a")
+                })
+                .map(|(_, _, value, _)| value)
+                .collect::<HashSet<_>>();
+            (path, ids)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut diagnostics = Diagnostics::default();
+    for (path, html) in pages.iter() {
+        let from = PublicPath(
+            path.strip_prefix(Path::new("public"))
+                .unwrap_or(path)
+                .to_path_buf(),
+        );
+        for (_, _, dest, offset) in
+            attributes(html).filter(|(_, name, _, _)| *name == "href" || *name == "src")
+        {
+            let (target, fragment) = match resolve(&from, &dest) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            if reported.contains(&target) {
+                continue;
+            }
+            let message = if !produced.contains(&target) {
+                format!("`{}` does not point at a generated file", dest)
+            } else {
+                match (fragment, ids.get(&target)) {
+                    (Some(fragment), Some(ids))
+                        if !fragment.is_empty() && !ids.contains(&fragment) =>
+                    {
+                        format!(
+                            "`{}` has no element with id `{}`",
+                            target.display(),
+                            fragment
+                        )
+                    }
+                    _ => continue,
+                }
+            };
+            diagnostics.push(Diagnostic::new(path.clone(), message).at(html, offset));
+        }
+    }
+    diagnostics
+}
+
+/// Every attribute in every tag of `html` as (lowercase element name,
+/// attribute name, decoded value, byte offset of the attribute). Good enough
+/// for the markup maud and ammonia produce; not a general HTML parser.
+fn attributes(html: &str) -> impl Iterator<Item = (String, String, String, usize)> + '_ {
+    html.match_indices('<')
+        .filter_map(move |(start, _)| {
+            let rest = &html[start + 1..];
+            if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                return None;
+            }
+            let end = tag_end(rest)?;
+            Some((start + 1, &rest[..end]))
+        })
+        .flat_map(|(offset, tag)| {
+            let element = tag
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            parse_attributes(tag)
+                .into_iter()
+                .map(move |(name, value, at)| (element.clone(), name, value, offset + at))
+        })
+}
+
+/// Index of the `>` closing a tag, skipping any inside quoted values.
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attributes(tag: &str) -> Vec<(String, String, usize)> {
+    let mut attributes = Vec::new();
+    let bytes = tag.as_bytes();
+    // Skip the element name
+    let mut i = tag
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(tag.len());
+    while i < bytes.len() {
+        while i < bytes.len() && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        let name_start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && bytes[i] != b'='
+            && bytes[i] != b'/'
+        {
+            i += 1;
+        }
+        if name_start == i {
+            break;
+        }
+        let name = tag[name_start..i].to_ascii_lowercase();
+        if i < bytes.len() && bytes[i] == b'=' {
+            i += 1;
+            let (value, next) = match bytes.get(i) {
+                Some(&q) if q == b'"' || q == b'\'' => {
+                    let end = tag[i + 1..]
+                        .find(q as char)
+                        .map(|e| i + 1 + e)
+                        .unwrap_or(tag.len());
+                    (&tag[(i + 1).min(end)..end], end + 1)
+                }
+                _ => {
+                    let end = tag[i..]
+                        .find(|c: char| c.is_whitespace())
+                        .map(|e| i + e)
+                        .unwrap_or(tag.len());
+                    (&tag[i..end], end)
+                }
+            };
+            attributes.push((name, decode_entities(value), name_start));
+            i = next;
+        } else {
+            attributes.push((name, String::new(), name_start));
+        }
+    }
+    attributes
+}
+
+/// Decodes the named entities escapers emit and any numeric character
+/// reference, such as the `&#x2f;` MiniJinja writes for `/`.
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end + 1));
+        let character = entity.and_then(|(name, _)| match name {
+            "quot" => Some('"'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "apos" => Some('\''),
+            _ => {
+                let number = name.strip_prefix('#')?;
+                let code = match number.strip_prefix(|c| c == 'x' || c == 'X') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => number.parse().ok()?,
+                };
+                std::char::from_u32(code)
+            }
+        });
+        match (character, entity) {
+            (Some(character), Some((_, len))) => {
+                decoded.push(character);
+                rest = &rest[len..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks `pages`, by path under `public/`, as the only files produced.
+    fn check(pages: &[(&str, &str)], others: &[&str]) -> Vec<String> {
+        check_reported(pages, others, &[])
+    }
+
+    /// Checks `pages` as `check` does, skipping links to `reported`.
+    fn check_reported(pages: &[(&str, &str)], others: &[&str], reported: &[&str]) -> Vec<String> {
+        let pages = pages
+            .iter()
+            .map(|(path, html)| (Path::new("public").join(path), html.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        let mut produced = pages.keys().cloned().collect::<BTreeSet<_>>();
+        produced.extend(others.iter().map(|path| Path::new("public").join(path)));
+        let reported = reported
+            .iter()
+            .map(|path| Path::new("public").join(path))
+            .collect();
+        check_links(&pages, &produced, &reported)
+            .iter()
+            .map(|d| d.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn resolves_relative_absolute_and_directory_links() {
+        assert_eq!(
+            resolve(&PublicPath(PathBuf::from("posts/a.html")), "b.html#top"),
+            Some((
+                PathBuf::from("public/posts/b.html"),
+                Some(String::from("top"))
+            ))
+        );
+        assert_eq!(
+            resolve(&PublicPath(PathBuf::from("posts/a.html")), "/tags/"),
+            Some((PathBuf::from("public/tags/index.html"), None))
+        );
+        assert_eq!(
+            resolve(
+                &PublicPath(PathBuf::from("posts/a.html")),
+                "../my%20cat.png"
+            ),
+            Some((PathBuf::from("public/my cat.png"), None))
+        );
+        assert_eq!(
+            resolve(
+                &PublicPath(PathBuf::from("posts/a.html")),
+                "https://example.com/"
+            ),
+            None
+        );
+        assert_eq!(
+            resolve(&PublicPath(PathBuf::from("posts/a.html")), ""),
+            None
+        );
+    }
+
+    #[test]
+    fn links_to_produced_files_are_fine() {
+        let problems = check(
+            &[
+                (
+                    "index.html",
+                    r#"<a href="/posts/a.html">A</a><img src="cat.png"><a href="mailto:me@example.com">Mail</a>"#,
+                ),
+                (
+                    "posts/a.html",
+                    r#"<a href="../">Home</a><a href="https://example.com/gone">Gone</a>"#,
+                ),
+            ],
+            &["cat.png"],
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn links_to_missing_files_are_errors() {
+        let problems = check(
+            &[(
+                "index.html",
+                r#"<a href="/posts/missing.html">?</a><img src='/dog.png'>"#,
+            )],
+            &[],
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "`/posts/missing.html` does not point at a generated file",
+                "`/dog.png` does not point at a generated file",
+            ]
+        );
+    }
+
+    #[test]
+    fn links_already_reported_are_skipped() {
+        let problems = check_reported(
+            &[(
+                "posts/a.html",
+                r#"<a href="/posts/bashdoc3.html">3</a><a href="/posts/gone.html">?</a>"#,
+            )],
+            &[],
+            &["posts/bashdoc3.html"],
+        );
+        assert_eq!(
+            problems,
+            vec!["`/posts/gone.html` does not point at a generated file"]
+        );
+    }
+
+    #[test]
+    fn fragments_need_an_id_or_a_named_anchor() {
+        let problems = check(
+            &[
+                (
+                    "index.html",
+                    r##"<a href="/posts/a.html#intro">1</a><a href="/posts/a.html#old">2</a><a href="/posts/a.html#field">3</a><a href="/posts/a.html#">4</a><a href="#nowhere">5</a>"##,
+                ),
+                (
+                    "posts/a.html",
+                    r#"<h2 id="intro">Intro</h2><a name="old"></a><input name="field">"#,
+                ),
+            ],
+            &[],
+        );
+        assert_eq!(
+            problems,
+            vec![
+                "`public/posts/a.html` has no element with id `field`",
+                "`public/index.html` has no element with id `nowhere`",
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_values_are_decoded() {
+        let problems = check(
+            &[
+                (
+                    "index.html",
+                    r#"<a href="/posts/a.html#caf&eacute;">1</a><a href="/posts/a.html#a&amp;b">2</a>"#,
+                ),
+                ("posts/a.html", r#"<h2 id="a&#38;b">A and B</h2>"#),
+            ],
+            &[],
+        );
+        assert_eq!(
+            problems,
+            vec!["`public/posts/a.html` has no element with id `caf&eacute;`"]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! What each page says about itself to search engines and link previews.
+use crate::{
+    config::{Config, MenuLink},
+    retrieve::{slugify, Post},
+};
+use chrono::{DateTime, FixedOffset};
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::Serialize;
+use serde_json::{json, Value};
+use url::Url;
+
+/// Longest description, in characters, before it is cut at a word.
+const DESCRIPTION_LENGTH: usize = 160;
+
+/// Hosts whose `/<owner>/<name>` URLs are source repositories.
+const REPOSITORY_HOSTS: &[&str] = &["github.com", "gitlab.com", "bitbucket.org", "codeberg.org"];
+
+/// The title, summary, canonical URL and preview image of a page. Every URL
+/// is absolute, from the configured `base_url`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Meta {
+    pub title: String,
+    pub site_name: String,
+    pub description: String,
+    pub url: String,
+    pub image: Option<String>,
+    /// Set for posts, which are shared as articles rather than websites.
+    pub article: Option<Article>,
+    /// From the home page down to this one. Empty for listings.
+    pub breadcrumbs: Vec<Breadcrumb>,
+    /// Links from the site config shown before the taxonomies.
+    pub menu: Vec<MenuLink>,
+    /// The index page of each way posts are grouped, for site navigation.
+    pub taxonomies: Vec<TaxonomyLink>,
+    /// The site's description, shown in the sidebar linking to `about`.
+    pub tagline: String,
+    pub about: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaxonomyLink {
+    pub name: String,
+    pub title: String,
+    /// From the site root.
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Article {
+    pub published: DateTime<FixedOffset>,
+    /// The frontmatter `updated`, or `published` when there is none.
+    pub modified: DateTime<FixedOffset>,
+    pub author: String,
+    pub tags: Vec<String>,
+    /// The project the post is about, from the frontmatter `link`.
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Breadcrumb {
+    pub name: String,
+    /// Absolute URL, when the step has a page of its own.
+    pub url: Option<String>,
+}
+
+impl Meta {
+    /// For a page with no post behind it, such as a listing, at `path` from
+    /// the site root.
+    pub fn page(config: &Config, title: &str, path: &str) -> Self {
+        Meta {
+            title: title.to_string(),
+            site_name: config.title.clone(),
+            description: config.description.clone(),
+            url: config.url(path),
+            image: None,
+            article: None,
+            breadcrumbs: Vec::new(),
+            menu: config.menu.clone(),
+            taxonomies: taxonomy_links(config),
+            tagline: config.description.clone(),
+            about: config.about.clone(),
+        }
+    }
+
+    /// For `post`, shared with the image at `card`, from the site root, when
+    /// it has none of its own.
+    pub fn post(config: &Config, post: &Post, card: Option<&str>) -> Self {
+        let url = config.url(post.public_path(false).0.to_str().unwrap());
+        let description = match post.frontmatter.description {
+            Some(ref description) => description.clone(),
+            None => excerpt(&post.content),
+        };
+        let image = post
+            .frontmatter
+            .image
+            .clone()
+            .or_else(|| first_image(&post.content).map(|src| post.resolve(&src)))
+            .or_else(|| {
+                post.frontmatter
+                    .gallery
+                    .first()
+                    .map(|image| image.src.clone())
+            })
+            .and_then(|src| Url::parse(&url).and_then(|url| url.join(&src)).ok())
+            .map(|url| url.to_string())
+            .or_else(|| card.map(|card| config.url(card)));
+        let article = if post.is_page() {
+            None
+        } else {
+            Some(Article {
+                published: post.date,
+                modified: post.frontmatter.updated.unwrap_or(post.date),
+                author: config.author.clone(),
+                tags: post.frontmatter.tags.clone(),
+                link: post.frontmatter.link.clone(),
+            })
+        };
+        let mut breadcrumbs = vec![Breadcrumb {
+            name: config.title.clone(),
+            url: Some(config.url("/")),
+        }];
+        // Sections have no page of their own, but the tag of the same name
+        // usually does
+        if let Some(section) = post.filename.section() {
+            let tag = slugify(&section);
+            breadcrumbs.push(Breadcrumb {
+                url: if post.frontmatter.tags.contains(&tag) {
+                    Some(config.url(&format!("/tags/{}.html", tag)))
+                } else {
+                    None
+                },
+                name: section,
+            });
+        }
+        breadcrumbs.push(Breadcrumb {
+            name: post.frontmatter.title.clone(),
+            url: Some(url.clone()),
+        });
+        Meta {
+            title: post.frontmatter.title.clone(),
+            site_name: config.title.clone(),
+            description,
+            url,
+            image,
+            article,
+            breadcrumbs,
+            menu: config.menu.clone(),
+            taxonomies: taxonomy_links(config),
+            tagline: config.description.clone(),
+            about: config.about.clone(),
+        }
+    }
+
+    /// schema.org data for posts: a `BlogPosting`, or for write-ups of a
+    /// project a `SoftwareSourceCode` when it links to a repository and a
+    /// `CreativeWork` otherwise, with the breadcrumbs leading to it.
+    pub fn json_ld(&self) -> Option<Value> {
+        let article = self.article.as_ref()?;
+        let mut work = json!({
+            "@type": "BlogPosting",
+            "headline": self.title,
+            "name": self.title,
+            "description": self.description,
+            "url": self.url,
+            "mainEntityOfPage": self.url,
+            "datePublished": article.published.to_rfc3339(),
+            "dateModified": article.modified.to_rfc3339(),
+            "author": {
+                "@type": "Person",
+                "name": article.author,
+            },
+            "keywords": article.tags.join(", "),
+        });
+        if let Some(ref image) = self.image {
+            work["image"] = json!(image);
+        }
+        if let Some(ref link) = article.link {
+            match repository(link) {
+                Some(repository) => {
+                    work["@type"] = json!("SoftwareSourceCode");
+                    work["codeRepository"] = json!(repository);
+                }
+                None => {
+                    work["@type"] = json!("CreativeWork");
+                    work["sameAs"] = json!(link);
+                }
+            }
+        }
+        let crumbs = self
+            .breadcrumbs
+            .iter()
+            .enumerate()
+            .map(|(i, crumb)| {
+                let mut item = json!({
+                    "@type": "ListItem",
+                    "position": i + 1,
+                    "name": crumb.name,
+                });
+                if let Some(ref url) = crumb.url {
+                    item["item"] = json!(url);
+                }
+                item
+            })
+            .collect::<Vec<_>>();
+        Some(json!({
+            "@context": "https://schema.org",
+            "@graph": [
+                work,
+                {
+                    "@type": "BreadcrumbList",
+                    "itemListElement": crumbs,
+                },
+            ],
+        }))
+    }
+
+    /// [`Meta::json_ld`] as text that can't close the `<script>` it is put
+    /// in.
+    pub fn json_ld_script(&self) -> Option<String> {
+        self.json_ld()
+            .map(|data| data.to_string().replace("</", "<\\/"))
+    }
+}
+
+/// The repository `link` is in, for links to a repository or a file in one.
+fn repository(link: &str) -> Option<String> {
+    let url = Url::parse(link).ok()?;
+    let host = url.host_str()?;
+    if host == "gist.github.com" {
+        return Some(link.to_string());
+    }
+    if !REPOSITORY_HOSTS.contains(&host) {
+        return None;
+    }
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let (owner, name) = (segments.next()?, segments.next()?);
+    Some(format!("{}://{}/{}/{}", url.scheme(), host, owner, name))
+}
+
+/// The prose at the start of `markdown` as plain text, cut at a word when
+/// it runs long.
+pub fn excerpt(markdown: &str) -> String {
+    let mut text = String::new();
+    // Code and image descriptions don't read as part of the prose
+    let mut skipping = 0;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Image(..)) => skipping += 1,
+            Event::End(Tag::CodeBlock(_)) | Event::End(Tag::Image(..)) => skipping -= 1,
+            Event::Text(ref words) | Event::Code(ref words) if skipping == 0 => {
+                text.push_str(words);
+                text.push(' ');
+            }
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            _ => {}
+        }
+        if text.chars().count() > DESCRIPTION_LENGTH {
+            break;
+        }
+    }
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let mut excerpt = String::new();
+    for word in words.iter() {
+        if excerpt.chars().count() + word.chars().count() + 1 > DESCRIPTION_LENGTH {
+            excerpt.push('…');
+            return excerpt;
+        }
+        if !excerpt.is_empty() {
+            excerpt.push(' ');
+        }
+        excerpt.push_str(word);
+    }
+    excerpt
+}
+
+fn taxonomy_links(config: &Config) -> Vec<TaxonomyLink> {
+    config
+        .all_taxonomies()
+        .iter()
+        .map(|taxonomy| TaxonomyLink {
+            name: taxonomy.name.clone(),
+            title: taxonomy.title(),
+            url: taxonomy.index_url(),
+        })
+        .collect()
+}
+
+/// Where the first image in `markdown` is, as written.
+pub fn first_image(markdown: &str) -> Option<String> {
+    Parser::new(markdown).find_map(|event| match event {
+        Event::Start(Tag::Image(_, src, _)) => Some(src.to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        retrieve::{FrontMatter, SourcePath},
+        templates::layout::meta_tags,
+    };
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    fn post(path: &str, content: &str) -> Post {
+        Post {
+            frontmatter: FrontMatter {
+                title: String::from("Hello"),
+                tags: vec![String::from("rust"), String::from("cli")],
+                ..FrontMatter::default()
+            },
+            content: content.to_string(),
+            filename: SourcePath(PathBuf::from(path)),
+            hash: String::new(),
+            date: DateTime::parse_from_rfc3339("2021-03-04T00:00:00-05:00").unwrap(),
+            tag_names: BTreeMap::new(),
+            written_tags: Vec::new(),
+            linked: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn posts_describe_themselves_from_their_content() {
+        let config = Config::default();
+        let meta = Meta::post(
+            &config,
+            &post(
+                "content/rust/hello.md",
+                "Some `code` first.\n\n![a cat](/images/cat.png)\n",
+            ),
+            Some("/cards/posts/hello.png"),
+        );
+        assert_eq!(meta.url, "https://dustinknopoff.dev/posts/hello.html");
+        assert_eq!(meta.description, "Some code first.");
+        // The post's own image beats its card
+        assert_eq!(
+            meta.image.as_deref(),
+            Some("https://dustinknopoff.dev/images/cat.png")
+        );
+        let article = meta.article.unwrap();
+        assert_eq!(article.modified, article.published);
+        assert_eq!(article.tags, vec!["rust", "cli"]);
+        let crumbs = meta
+            .breadcrumbs
+            .iter()
+            .map(|crumb| (crumb.name.as_str(), crumb.url.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            crumbs,
+            vec![
+                ("Dustin Knopoff", Some("https://dustinknopoff.dev/")),
+                ("rust", Some("https://dustinknopoff.dev/tags/rust.html")),
+                ("Hello", Some("https://dustinknopoff.dev/posts/hello.html")),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_card_is_only_shared_when_given() {
+        let config = Config::default();
+        let plain = post("content/rust/hello.md", "No pictures.");
+        assert_eq!(
+            Meta::post(&config, &plain, Some("/cards/posts/hello.png"))
+                .image
+                .as_deref(),
+            Some("https://dustinknopoff.dev/cards/posts/hello.png")
+        );
+        assert_eq!(Meta::post(&config, &plain, None).image, None);
+    }
+
+    #[test]
+    fn pages_are_websites_without_structured_data() {
+        let config = Config::default();
+        let meta = Meta::post(&config, &post("content/pages/about.md", "Hi"), None);
+        assert!(meta.article.is_none());
+        assert!(meta.json_ld().is_none());
+        let tags = meta_tags(&meta).into_string();
+        assert!(tags.contains(r#"<meta property="og:type" content="website">"#));
+        assert!(tags.contains(r#"<meta name="twitter:card" content="summary">"#));
+        assert!(!tags.contains("og:image"));
+    }
+
+    #[test]
+    fn og_tags_describe_the_post() {
+        let config = Config::default();
+        let meta = Meta::post(
+            &config,
+            &post("content/rust/hello.md", "Hi"),
+            Some("/cards/posts/hello.png"),
+        );
+        let tags = meta_tags(&meta).into_string();
+        for tag in [
+            r#"<link rel="canonical" href="https://dustinknopoff.dev/posts/hello.html">"#,
+            r#"<meta property="og:title" content="Hello">"#,
+            r#"<meta property="og:type" content="article">"#,
+            r#"<meta property="og:image" content="https://dustinknopoff.dev/cards/posts/hello.png">"#,
+            r#"<meta property="article:published_time" content="2021-03-04T00:00:00-05:00">"#,
+            r#"<meta property="article:tag" content="cli">"#,
+            r#"<meta name="twitter:card" content="summary_large_image">"#,
+            r#"<script type="application/ld+json">"#,
+        ] {
+            assert!(tags.contains(tag), "no {} in {}", tag, tags);
+        }
+    }
+
+    #[test]
+    fn json_ld_describes_posts_and_projects() {
+        let config = Config::default();
+        let mut hello = post("content/rust/hello.md", "Hi");
+        let data = Meta::post(&config, &hello, None).json_ld().unwrap();
+        let work = &data["@graph"][0];
+        assert_eq!(work["@type"], "BlogPosting");
+        assert_eq!(work["headline"], "Hello");
+        assert_eq!(work["keywords"], "rust, cli");
+        assert_eq!(work["datePublished"], "2021-03-04T00:00:00-05:00");
+        assert!(work.get("image").is_none());
+        let crumbs = &data["@graph"][1]["itemListElement"];
+        assert_eq!(crumbs[2]["position"], 3);
+        assert_eq!(crumbs[2]["name"], "Hello");
+
+        hello.frontmatter.link = Some(String::from(
+            "https://github.com/dustinknopoff/bashdoc/blob/master/README.md",
+        ));
+        let data = Meta::post(&config, &hello, None).json_ld().unwrap();
+        assert_eq!(data["@graph"][0]["@type"], "SoftwareSourceCode");
+        assert_eq!(
+            data["@graph"][0]["codeRepository"],
+            "https://github.com/dustinknopoff/bashdoc"
+        );
+
+        hello.frontmatter.link = Some(String::from("https://example.com/app"));
+        let data = Meta::post(&config, &hello, None).json_ld().unwrap();
+        assert_eq!(data["@graph"][0]["@type"], "CreativeWork");
+        assert_eq!(data["@graph"][0]["sameAs"], "https://example.com/app");
+    }
+
+    #[test]
+    fn json_ld_cannot_close_its_script() {
+        let config = Config::default();
+        let mut hello = post("content/rust/hello.md", "Hi");
+        hello.frontmatter.title = String::from("</script><script>alert(1)");
+        let script = Meta::post(&config, &hello, None).json_ld_script().unwrap();
+        assert!(!script.contains("</"));
+        assert!(script.contains(r"<\/script>"));
+    }
+
+    #[test]
+    fn repositories_are_found_from_links_into_them() {
+        assert_eq!(
+            repository("https://gitlab.com/owner/name/-/tree/main").as_deref(),
+            Some("https://gitlab.com/owner/name")
+        );
+        assert_eq!(
+            repository("https://gist.github.com/owner/abc123").as_deref(),
+            Some("https://gist.github.com/owner/abc123")
+        );
+        assert_eq!(repository("https://github.com/owner"), None);
+        assert_eq!(repository("https://example.com/owner/name"), None);
+    }
+
+    #[test]
+    fn excerpts_skip_code_and_images_and_cut_at_a_word() {
+        assert_eq!(
+            excerpt("![a cat](cat.png)\n\n```\nlet x = 1;\n```\n\nA *short* intro."),
+            "A short intro."
+        );
+        let long = excerpt(&"word ".repeat(100));
+        assert!(long.ends_with("word…"));
+        assert!(long.chars().count() <= DESCRIPTION_LENGTH + 1);
+    }
+}
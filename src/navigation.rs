@@ -0,0 +1,97 @@
+//! Links from a post to the posts around it, computed in the database and
+//! handed to layouts alongside the post.
+use crate::retrieve::{slugify, Post, SourcePath};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Enough of another post to link to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PostRef {
+    pub title: String,
+    /// Path from the site root, starting with `/`.
+    pub url: String,
+}
+
+impl PostRef {
+    pub fn new(post: &Post) -> Self {
+        PostRef {
+            title: post.frontmatter.title.clone(),
+            url: format!("/{}", post.public_path(false).0.display()),
+        }
+    }
+}
+
+/// The posts either side of one in date order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Neighbours {
+    /// The next older post.
+    pub previous: Option<PostRef>,
+    /// The next newer post.
+    pub next: Option<PostRef>,
+}
+
+impl Neighbours {
+    pub fn is_empty(&self) -> bool {
+        self.previous.is_none() && self.next.is_none()
+    }
+}
+
+/// The series a post is part of.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Series {
+    pub name: String,
+    /// The series' index page.
+    pub url: String,
+    /// One-based position of the post in `parts`.
+    pub part: usize,
+    /// Every part, in order.
+    pub parts: Vec<PostRef>,
+}
+
+impl Series {
+    /// Where the index page for the series `name` is, from the site root.
+    pub fn url(name: &str) -> String {
+        format!("/series/{}.html", slugify(name))
+    }
+}
+
+/// Which posts to find neighbours among.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Scope {
+    All,
+    /// Posts in the same directory under `content/`.
+    Section(String),
+    Tag(String),
+}
+
+/// Everything a post page links to besides its own content. Pages get an
+/// empty one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Navigation {
+    /// Neighbours among every post.
+    #[serde(flatten)]
+    pub all: Neighbours,
+    /// The directory under `content/` the post is in.
+    pub section: Option<String>,
+    /// Neighbours among the posts in the same section.
+    pub in_section: Neighbours,
+    /// Neighbours among the posts sharing each of the post's tags.
+    pub tags: BTreeMap<String, Neighbours>,
+    /// The most closely related posts, best first.
+    pub related: Vec<PostRef>,
+    pub series: Option<Series>,
+}
+
+/// Index of `key` in `posts` and the paths either side of it.
+pub(crate) fn adjacent<'a>(
+    posts: &'a [SourcePath],
+    key: &SourcePath,
+) -> (Option<&'a SourcePath>, Option<&'a SourcePath>) {
+    match posts.iter().position(|path| path == key) {
+        Some(index) => (
+            index.checked_sub(1).and_then(|i| posts.get(i)),
+            posts.get(index + 1),
+        ),
+        None => (None, None),
+    }
+}
@@ -0,0 +1,345 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    fs::{self, DirBuilder, File},
+    hash::{Hash, Hasher},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Name of the file, inside the cache directory, listing every file the
+/// previous build produced. Older builds kept it in the output directory.
+const MANIFEST: &str = ".build-manifest";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Updated,
+    Removed,
+}
+
+/// Every write into the output directory goes through here so unchanged
+/// files keep their mtimes and files no source produces any more get removed.
+/// Nothing touches the disk until [`Output::finish`], so a build that fails
+/// part way leaves the previous output as it was.
+pub struct Output {
+    root: PathBuf,
+    /// Where the manifest is kept, out of what gets published.
+    cache_dir: PathBuf,
+    dry_run: bool,
+    produced: BTreeSet<PathBuf>,
+    /// Hash of what was last written to each path this build.
+    digests: BTreeMap<PathBuf, u64>,
+    /// Paths written more than once with different contents, where all but
+    /// the last write were lost.
+    collisions: BTreeSet<PathBuf>,
+    /// Contents of every HTML page produced, kept for link checking.
+    pages: BTreeMap<PathBuf, String>,
+    /// Files that differ from what's on disk, and what to write to them.
+    /// Contents aren't kept in a dry run.
+    pending: BTreeMap<PathBuf, (Change, Vec<u8>)>,
+}
+
+impl Output {
+    pub fn new(root: impl Into<PathBuf>, cache_dir: impl Into<PathBuf>, dry_run: bool) -> Self {
+        Output {
+            root: root.into(),
+            cache_dir: cache_dir.into(),
+            dry_run,
+            produced: BTreeSet::new(),
+            digests: BTreeMap::new(),
+            collisions: BTreeSet::new(),
+            pages: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Every path written (or, in a dry run, that would have been) so far.
+    pub fn produced(&self) -> &BTreeSet<PathBuf> {
+        &self.produced
+    }
+
+    pub fn pages(&self) -> &BTreeMap<PathBuf, String> {
+        &self.pages
+    }
+
+    /// An error for each path that two different outputs were written to,
+    /// such as a page whose slug matches a generated taxonomy page.
+    pub fn collisions(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+        for path in self.collisions.iter() {
+            diagnostics.push(Diagnostic::new(
+                path.clone(),
+                format!(
+                    "{} is generated more than once, with different contents each time",
+                    path.display()
+                ),
+            ));
+        }
+        diagnostics
+    }
+
+    /// Queues `contents` to be written to `path` unless the file already
+    /// holds exactly that. Writing the same contents to a path twice, as for
+    /// an image two posts share, is fine.
+    pub fn write<P: AsRef<Path>>(&mut self, path: P, contents: &[u8]) -> Result<(), anyhow::Error> {
+        let path = path.as_ref();
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        let digest = hasher.finish();
+        if let Some(previous) = self.digests.insert(path.to_path_buf(), digest) {
+            if previous != digest {
+                self.collisions.insert(path.to_path_buf());
+            }
+        }
+        self.produced.insert(path.to_path_buf());
+        if path.extension().map(|e| e == "html").unwrap_or(false) {
+            self.pages.insert(
+                path.to_path_buf(),
+                String::from_utf8_lossy(contents).into_owned(),
+            );
+        }
+        let change = match File::open(path) {
+            Ok(mut existing) => {
+                let mut old_contents = Vec::new();
+                existing.read_to_end(&mut old_contents)?;
+                if old_contents == contents {
+                    self.pending.remove(path);
+                    return Ok(());
+                }
+                Change::Updated
+            }
+            Err(_) => Change::Created,
+        };
+        let contents = if self.dry_run {
+            Vec::new()
+        } else {
+            contents.to_vec()
+        };
+        self.pending.insert(path.to_path_buf(), (change, contents));
+        Ok(())
+    }
+
+    /// Removes the directories above the removed file `path` that are left
+    /// empty, such as those of a renamed taxonomy, up to the output root.
+    fn remove_empty_parents(&self, path: &Path) -> Result<(), anyhow::Error> {
+        for dir in path.ancestors().skip(1) {
+            if dir == self.root || !dir.starts_with(&self.root) {
+                break;
+            }
+            if fs::read_dir(dir)?.next().is_some() {
+                break;
+            }
+            fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.cache_dir.join(MANIFEST)
+    }
+
+    fn legacy_manifest_path(&self) -> PathBuf {
+        self.root.join(MANIFEST)
+    }
+
+    fn previous_manifest(&self) -> BTreeSet<PathBuf> {
+        fs::read_to_string(self.manifest_path())
+            .or_else(|_| fs::read_to_string(self.legacy_manifest_path()))
+            .map(|manifest| manifest.lines().map(PathBuf::from).collect())
+            .unwrap_or_default()
+    }
+
+    /// Writes every changed file, removes outputs of the previous build that
+    /// were not produced by this one, then records this build's manifest.
+    pub fn finish(self) -> Result<Vec<(Change, PathBuf)>, anyhow::Error> {
+        let mut changes = Vec::with_capacity(self.pending.len());
+        for (path, (change, contents)) in self.pending.iter() {
+            if !self.dry_run {
+                if let Some(parent) = path.parent() {
+                    if !parent.exists() {
+                        DirBuilder::new().recursive(true).create(parent)?;
+                    }
+                }
+                let mut file = File::create(path)?;
+                file.write_all(contents)?;
+            }
+            changes.push((*change, path.clone()));
+        }
+        for stale in self.previous_manifest().difference(&self.produced) {
+            if !stale.exists() {
+                continue;
+            }
+            if !self.dry_run {
+                fs::remove_file(stale)?;
+                self.remove_empty_parents(stale)?;
+            }
+            changes.push((Change::Removed, stale.clone()));
+        }
+        if !self.dry_run {
+            let manifest = self
+                .produced
+                .iter()
+                .filter_map(|path| path.to_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let manifest_path = self.manifest_path();
+            if fs::read_to_string(&manifest_path).ok().as_deref() != Some(manifest.as_str()) {
+                if !self.cache_dir.exists() {
+                    DirBuilder::new().recursive(true).create(&self.cache_dir)?;
+                }
+                fs::write(manifest_path, manifest)?;
+            }
+            let legacy = self.legacy_manifest_path();
+            if legacy.exists() {
+                fs::remove_file(legacy)?;
+            }
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of the test's own.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portfolio-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Builds into `dir/public` with whatever `files` holds, as a real run
+    /// would.
+    fn build(dir: &Path, dry_run: bool, files: &[(&str, &str)]) -> Vec<(Change, PathBuf)> {
+        let mut out = Output::new(dir.join("public"), dir.join("cache"), dry_run);
+        for (path, contents) in files {
+            out.write(dir.join("public").join(path), contents.as_bytes())
+                .unwrap();
+        }
+        out.finish().unwrap()
+    }
+
+    #[test]
+    fn only_changed_files_are_written() {
+        let dir = scratch("output-changes");
+        let index = dir.join("public/index.html");
+        assert_eq!(
+            build(&dir, false, &[("index.html", "one")]),
+            vec![(Change::Created, index.clone())]
+        );
+        assert_eq!(fs::read_to_string(&index).unwrap(), "one");
+        assert!(build(&dir, false, &[("index.html", "one")]).is_empty());
+        assert_eq!(
+            build(&dir, false, &[("index.html", "two")]),
+            vec![(Change::Updated, index.clone())]
+        );
+        assert_eq!(fs::read_to_string(&index).unwrap(), "two");
+    }
+
+    #[test]
+    fn the_manifest_is_kept_out_of_the_output() {
+        let dir = scratch("output-manifest");
+        build(&dir, false, &[("index.html", "one"), ("posts/a.html", "a")]);
+        let manifest = fs::read_to_string(dir.join("cache").join(MANIFEST)).unwrap();
+        let listed = manifest.lines().map(PathBuf::from).collect::<BTreeSet<_>>();
+        assert_eq!(
+            listed,
+            vec![
+                dir.join("public/index.html"),
+                dir.join("public/posts/a.html")
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert!(!dir.join("public").join(MANIFEST).exists());
+    }
+
+    #[test]
+    fn files_no_longer_produced_are_removed() {
+        let dir = scratch("output-stale");
+        build(
+            &dir,
+            false,
+            &[("index.html", "one"), ("tags/old/index.html", "old")],
+        );
+        fs::write(dir.join("public/CNAME"), "example.com").unwrap();
+        let stale = dir.join("public/tags/old/index.html");
+        assert_eq!(
+            build(&dir, false, &[("index.html", "one")]),
+            vec![(Change::Removed, stale.clone())]
+        );
+        assert!(!stale.exists());
+        assert!(!dir.join("public/tags").exists());
+        assert!(dir.join("public/index.html").exists());
+        // Never produced by a build, so never the build's to remove
+        assert!(dir.join("public/CNAME").exists());
+    }
+
+    #[test]
+    fn a_manifest_in_the_output_is_still_read_then_moved() {
+        let dir = scratch("output-legacy");
+        build(&dir, false, &[("index.html", "one"), ("old.html", "old")]);
+        fs::rename(
+            dir.join("cache").join(MANIFEST),
+            dir.join("public").join(MANIFEST),
+        )
+        .unwrap();
+        assert_eq!(
+            build(&dir, false, &[("index.html", "one")]),
+            vec![(Change::Removed, dir.join("public/old.html"))]
+        );
+        assert!(!dir.join("public").join(MANIFEST).exists());
+        assert!(dir.join("cache").join(MANIFEST).exists());
+    }
+
+    #[test]
+    fn a_dry_run_reports_without_touching_the_disk() {
+        let dir = scratch("output-dry-run");
+        build(&dir, false, &[("index.html", "one"), ("old.html", "old")]);
+        let manifest = fs::read_to_string(dir.join("cache").join(MANIFEST)).unwrap();
+        let changes = build(&dir, true, &[("index.html", "two"), ("new.html", "new")]);
+        assert_eq!(
+            changes,
+            vec![
+                (Change::Updated, dir.join("public/index.html")),
+                (Change::Created, dir.join("public/new.html")),
+                (Change::Removed, dir.join("public/old.html")),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("public/index.html")).unwrap(),
+            "one"
+        );
+        assert!(!dir.join("public/new.html").exists());
+        assert!(dir.join("public/old.html").exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("cache").join(MANIFEST)).unwrap(),
+            manifest
+        );
+    }
+
+    #[test]
+    fn writing_different_contents_twice_is_a_collision() {
+        let dir = scratch("output-collisions");
+        let mut out = Output::new(dir.join("public"), dir.join("cache"), true);
+        let shared = dir.join("public/shared.png");
+        out.write(&shared, b"image").unwrap();
+        out.write(&shared, b"image").unwrap();
+        assert!(out.collisions().is_empty());
+        out.write(dir.join("public/tags/index.html"), b"page")
+            .unwrap();
+        out.write(dir.join("public/tags/index.html"), b"taxonomy")
+            .unwrap();
+        assert_eq!(out.collisions().len(), 1);
+        assert_eq!(out.produced().len(), 2);
+        assert_eq!(out.pages().len(), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{self, DirBuilder, File},
+    io::{Read, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// Bump whenever the shape of `Rendered`, the markdown rendering or how
+/// images are encoded changes in a way that should invalidate everything
+/// already on disk.
+const CACHE_VERSION: u32 = 3;
+
+/// The expensive-to-produce output of a single post: the highlighted,
+/// sanitized body, its plain-text blurb and the grid of its frontmatter
+/// gallery, if it has one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rendered {
+    pub content: String,
+    pub blurb: String,
+    #[serde(default)]
+    pub gallery: String,
+}
+
+/// Hash of `source`, such as a post's raw source or anything else a cache
+/// entry is keyed on, salted with the generator version so a new release of
+/// the generator never reuses stale output.
+pub fn content_hash(source: &str) -> String {
+    file_hash(source.as_bytes())
+}
+
+/// [`content_hash`] for files that aren't text, such as images.
+pub fn file_hash(contents: &[u8]) -> String {
+    let mut salted = format!(
+        "{}@{}#{}\0",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        CACHE_VERSION,
+    )
+    .into_bytes();
+    salted.extend_from_slice(contents);
+    format!("{:016x}", seahash::hash(&salted))
+}
+
+/// Extensions of the files the cache writes, so pruning leaves anything else
+/// alone.
+const ENTRY_EXTENSIONS: &[&str] = &["json", "png", "jpg", "webp", "avif"];
+
+/// Rendered post content persisted across runs in `dir`, one JSON file per
+/// content hash, and generated images, one file each.
+pub struct BuildCache {
+    dir: PathBuf,
+    /// File names of the entries read or written during this run.
+    used: HashSet<String>,
+}
+
+impl BuildCache {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        BuildCache {
+            dir: dir.into(),
+            used: HashSet::new(),
+        }
+    }
+
+    fn entry_name(hash: &str, extension: &str) -> String {
+        format!("{}.{}", hash, extension)
+    }
+
+    fn entry_path(&self, hash: &str, extension: &str) -> PathBuf {
+        self.dir.join(Self::entry_name(hash, extension))
+    }
+
+    /// Looks up a previous render. Unreadable or outdated entries are
+    /// treated as misses.
+    pub fn get(&mut self, hash: &str) -> Option<Arc<Rendered>> {
+        let mut file = File::open(self.entry_path(hash, "json")).ok()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).ok()?;
+        match serde_json::from_str(&contents) {
+            Ok(rendered) => {
+                self.used.insert(Self::entry_name(hash, "json"));
+                Some(Arc::new(rendered))
+            }
+            Err(e) => {
+                log::debug!("Ignoring corrupt cache entry {}: {}", hash, e);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, hash: &str, rendered: &Rendered) -> Result<(), anyhow::Error> {
+        self.insert_file(hash, "json", serde_json::to_string(rendered)?.as_bytes())
+    }
+
+    /// Looks up a previously generated file, such as an image.
+    pub fn get_file(&mut self, hash: &str, extension: &str) -> Option<Vec<u8>> {
+        let contents = fs::read(self.entry_path(hash, extension)).ok()?;
+        self.used.insert(Self::entry_name(hash, extension));
+        Some(contents)
+    }
+
+    pub fn insert_file(
+        &mut self,
+        hash: &str,
+        extension: &str,
+        contents: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        if self.used.insert(Self::entry_name(hash, extension)) {
+            if !self.dir.exists() {
+                log::debug!("{} did not exist. Creating now", self.dir.display());
+                DirBuilder::new().recursive(true).create(&self.dir)?;
+            }
+            let mut file = File::create(self.entry_path(hash, extension))?;
+            file.write_all(contents)?;
+        }
+        Ok(())
+    }
+
+    /// Removes every entry that was neither read nor written during this run.
+    pub fn prune(&self) -> Result<usize, anyhow::Error> {
+        let mut removed = 0;
+        if !self.dir.exists() {
+            return Ok(removed);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let stale = path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .map(|s| !self.used.contains(s))
+                .unwrap_or(false);
+            let entry = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| ENTRY_EXTENSIONS.contains(&e))
+                .unwrap_or(false);
+            if stale && entry {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of the test's own.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portfolio-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rendered(content: &str) -> Rendered {
+        Rendered {
+            content: content.to_string(),
+            blurb: String::from("blurb"),
+            gallery: String::new(),
+        }
+    }
+
+    #[test]
+    fn hashes_differ_by_contents() {
+        assert_eq!(content_hash("post"), content_hash("post"));
+        assert_ne!(content_hash("post"), content_hash("post!"));
+        assert_eq!(content_hash("post"), file_hash(b"post"));
+    }
+
+    #[test]
+    fn entries_are_read_back_in_a_later_run() {
+        let dir = scratch("persist-round-trip");
+        let mut cache = BuildCache::open(&dir);
+        cache.insert("abc", &rendered("<p>hi</p>")).unwrap();
+        cache.insert_file("def", "png", b"image").unwrap();
+
+        let mut cache = BuildCache::open(&dir);
+        assert_eq!(cache.get("abc").as_deref(), Some(&rendered("<p>hi</p>")));
+        assert_eq!(cache.get_file("def", "png"), Some(b"image".to_vec()));
+        assert!(cache.get("missing").is_none());
+        assert!(cache.get_file("abc", "png").is_none());
+    }
+
+    #[test]
+    fn corrupt_entries_are_misses() {
+        let dir = scratch("persist-corrupt");
+        fs::write(dir.join("abc.json"), "{not json").unwrap();
+        assert!(BuildCache::open(&dir).get("abc").is_none());
+    }
+
+    #[test]
+    fn prune_removes_only_unused_entries() {
+        let dir = scratch("persist-prune");
+        let mut cache = BuildCache::open(&dir);
+        cache.insert("kept", &rendered("kept")).unwrap();
+        cache.insert("stale", &rendered("stale")).unwrap();
+        cache.insert_file("old", "webp", b"image").unwrap();
+        fs::write(dir.join("notes.txt"), "not the cache's").unwrap();
+
+        let mut cache = BuildCache::open(&dir);
+        assert!(cache.get("kept").is_some());
+        assert_eq!(cache.prune().unwrap(), 2);
+        assert!(dir.join("kept.json").exists());
+        assert!(!dir.join("stale.json").exists());
+        assert!(!dir.join("old.webp").exists());
+        assert!(dir.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn pruning_a_missing_directory_removes_nothing() {
+        let dir = scratch("persist-missing").join("never-created");
+        assert_eq!(BuildCache::open(&dir).prune().unwrap(), 0);
+    }
+}
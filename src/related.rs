@@ -0,0 +1,108 @@
+//! Scoring how closely two posts are related.
+use pulldown_cmark::{Event, Parser, Tag};
+use std::collections::BTreeMap;
+
+/// Words too common to say anything about what a post is about.
+const STOP_WORDS: &[&str] = &[
+    "about", "after", "all", "also", "and", "any", "are", "because", "been", "but", "can", "could",
+    "did", "does", "doing", "don", "each", "for", "from", "get", "had", "has", "have", "how",
+    "into", "its", "just", "like", "more", "most", "not", "now", "one", "only", "other", "our",
+    "out", "over", "some", "such", "than", "that", "the", "their", "them", "then", "there",
+    "these", "they", "this", "through", "too", "use", "very", "was", "way", "were", "what", "when",
+    "where", "which", "while", "who", "why", "will", "with", "would", "you", "your",
+];
+
+/// How often each word appears in the prose of a markdown document. Code
+/// blocks are left out.
+pub fn count_terms(markdown: &str) -> BTreeMap<String, u32> {
+    let mut counts = BTreeMap::new();
+    let mut in_code_block = false;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            Event::Text(text) if !in_code_block => {
+                for word in text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .map(str::to_lowercase)
+                    .filter(|word| word.chars().count() > 2 && !STOP_WORDS.contains(&word.as_str()))
+                {
+                    *counts.entry(word).or_insert(0) += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    counts
+}
+
+/// Cosine similarity of two documents' TF-IDF vectors, between 0 and 1.
+/// `documents` is how many documents there are and `frequencies` how many
+/// of them contain each word.
+pub fn similarity(
+    a: &BTreeMap<String, u32>,
+    b: &BTreeMap<String, u32>,
+    frequencies: &BTreeMap<String, u32>,
+    documents: usize,
+) -> f64 {
+    let weight = |word: &str, count: u32| {
+        let containing = frequencies.get(word).copied().unwrap_or(1).max(1) as f64;
+        f64::from(count) * (documents as f64 / containing).ln()
+    };
+    let norm = |counts: &BTreeMap<String, u32>| {
+        counts
+            .iter()
+            .map(|(word, &count)| weight(word, count).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    };
+    let dot = a
+        .iter()
+        .filter_map(|(word, &count)| {
+            b.get(word)
+                .map(|&other| weight(word, count) * weight(word, other))
+        })
+        .sum::<f64>();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_prose_words_but_not_code() {
+        let counts = count_terms(
+            "Rust is fast. *Rust* and the borrow checker.\n\n```rust\nfn rust() {}\n```\n",
+        );
+        let expected = vec![("borrow", 1), ("checker", 1), ("fast", 1), ("rust", 2)]
+            .into_iter()
+            .map(|(word, count)| (word.to_string(), count))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn similarity_is_between_zero_and_one() {
+        let rust = count_terms("Rust borrow checker lifetimes");
+        let cooking = count_terms("Pasta sauce garlic");
+        let both = count_terms("Rust lifetimes and garlic");
+        let mut frequencies = BTreeMap::new();
+        for counts in [&rust, &cooking, &both] {
+            for word in counts.keys() {
+                *frequencies.entry(word.clone()).or_insert(0) += 1;
+            }
+        }
+        let score = |a, b| similarity(a, b, &frequencies, 3);
+        assert!((score(&rust, &rust) - 1.0).abs() < 1e-9);
+        assert_eq!(score(&rust, &cooking), 0.0);
+        assert!(score(&rust, &both) > 0.0 && score(&rust, &both) < 1.0);
+        assert!(score(&rust, &both) > score(&cooking, &both));
+        assert_eq!(score(&rust, &BTreeMap::new()), 0.0);
+    }
+}
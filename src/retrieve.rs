@@ -1,27 +1,100 @@
-use crate::templates::{layout::layout, markdown::Markdown};
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics},
+    gallery::GalleryImage,
+    links,
+    output::Output,
+    persist::content_hash,
+    templates::project::ProjectLink,
+};
 use chrono::prelude::*;
-use maud::{html, Markup};
-use serde::Deserialize;
-use std::{fmt, fs::File, io::Read, io::Write, path::PathBuf};
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
 use walkdir::{DirEntry, WalkDir};
 #[derive(Debug, Clone)]
 pub struct Post {
     pub frontmatter: FrontMatter,
     pub content: String,
     pub filename: SourcePath,
+    /// Hash of the raw source file, which the on-disk cache key starts from.
+    pub hash: String,
+    /// The frontmatter `date`, or when the file was last committed to git or,
+    /// failing that, modified.
+    pub date: DateTime<FixedOffset>,
+    /// How to show each of the frontmatter `tags`, by slug, from `tags.yaml`.
+    pub tag_names: BTreeMap<String, String>,
+    /// The frontmatter `tags` as written, before they were made canonical.
+    pub written_tags: Vec<String>,
+    /// Where each post or page the body links to by its Markdown file is
+    /// published, by the file's path.
+    pub linked: BTreeMap<PathBuf, PublicPath>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Every field is optional. A file without any frontmatter is a post titled
+/// after its filename and dated by its last commit, or by when it was last
+/// modified if it isn't committed yet.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct FrontMatter {
     pub tags: Vec<String>,
     pub title: String,
-    pub link: String,
+    pub link: Option<String>,
     #[serde(deserialize_with = "from_frontmatter")]
-    pub date: DateTime<FixedOffset>,
+    pub date: Option<DateTime<FixedOffset>>,
     #[serde(rename = "linkText")]
     pub link_text: Option<String>,
+    /// Overrides whether this is a post or a page, which otherwise depends
+    /// on whether the file lives under `content/pages/`.
+    #[serde(rename = "type")]
+    pub kind: Option<Kind>,
+    /// Name of a registered layout to render with instead of the default.
+    pub layout: Option<String>,
+    /// Name of the series of posts this is a part of.
+    pub series: Option<String>,
+    /// Position within the series. Parts without one follow those with one,
+    /// by date.
+    pub series_order: Option<u32>,
+    /// When the post was last revised, if after its `date`.
+    #[serde(deserialize_with = "from_frontmatter")]
+    pub updated: Option<DateTime<FixedOffset>>,
+    /// Summary for search results and link previews. Defaults to the start
+    /// of the post.
+    pub description: Option<String>,
+    /// Image for link previews. Defaults to the first image in the post.
+    pub image: Option<String>,
+    /// Images shown as a grid. See [`crate::gallery`].
+    pub gallery: Vec<GalleryImage>,
+    /// Site paths the post used to be at, such as `/posts/about.html`, each
+    /// of which gets a page redirecting to where it is now.
+    pub aliases: Vec<String>,
+    /// Any other fields, passed through untouched for templates to use.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_yaml::Value>,
+}
+
+/// Posts are dated entries that show up in listings, tags and the feed.
+/// Pages stand alone at a top-level URL.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Post,
+    Page,
 }
 
+/// Directory under `content/` whose files are pages rather than posts.
+const PAGES_DIR: &str = "pages";
+
+/// Name of the post in a bundle's directory.
+const BUNDLE_INDEX: &str = "index.md";
+
 use serde::de;
 
 struct DateTimeVisitor;
@@ -32,7 +105,7 @@ impl<'de> de::Visitor<'de> for DateTimeVisitor {
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         write!(
             formatter,
-            "a string represents chrono::DateTime<FixedOffset>"
+            "a date like `2018-06-26 08:31`, `2018-06-26` or `2018-06-26T08:31:00-05:00`"
         )
     }
 
@@ -40,49 +113,307 @@ impl<'de> de::Visitor<'de> for DateTimeVisitor {
     where
         E: de::Error,
     {
-        let hour = 3600;
-        let datetime = FixedOffset::west(5 * hour);
-        let str = format!("{} {}", s, datetime);
-        // 2018-06-26 08:31
-        match DateTime::parse_from_str(&str, "%Y-%m-%d %H:%M %:z") {
-            Ok(t) => Ok(t),
-            Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(s), &self)),
+        parse_date(s).ok_or_else(|| de::Error::invalid_value(de::Unexpected::Str(s), &self))
+    }
+
+    /// TOML's own dates and times, as in `date = 2018-06-26`, arrive as a
+    /// map holding their text.
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        match map.next_entry::<String, String>()? {
+            Some((_, s)) => self.visit_str(&s),
+            None => Err(de::Error::invalid_length(0, &self)),
         }
     }
 }
 
-fn from_frontmatter<'de, D>(d: D) -> Result<DateTime<FixedOffset>, D::Error>
+/// Parses a frontmatter date. Those without a UTC offset are taken to be in
+/// US Eastern Standard Time, and those without a time to be at midnight.
+fn parse_date(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Some(date);
+    }
+    let eastern = FixedOffset::west(5 * 3600);
+    let naive = ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })?;
+    eastern.from_local_datetime(&naive).single()
+}
+
+fn from_frontmatter<'de, D>(d: D) -> Result<Option<DateTime<FixedOffset>>, D::Error>
 where
     D: de::Deserializer<'de>,
 {
-    d.deserialize_str(DateTimeVisitor)
+    d.deserialize_any(DateTimeVisitor).map(Some)
+}
+
+/// When `file` was last committed to git, which unlike its modification
+/// time is the same in every checkout.
+fn commit_date(file: &Path) -> Option<DateTime<FixedOffset>> {
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%cI", "--"])
+        .arg(file)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    DateTime::parse_from_rfc3339(String::from_utf8_lossy(&output.stdout).trim()).ok()
+}
+
+/// When `file` was last modified, for files not committed to git yet.
+fn modified_date(file: &Path) -> Option<DateTime<FixedOffset>> {
+    let modified = fs::metadata(file).ok()?.modified().ok()?;
+    Some(DateTime::<Utc>::from(modified).into())
+}
+
+/// How a file's frontmatter block is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatterFormat {
+    /// Between `---` lines
+    Yaml,
+    /// Between `+++` lines
+    Toml,
+}
+
+/// Splits `source` into its frontmatter and body. Files without frontmatter
+/// come back with an empty YAML block.
+fn split_frontmatter(source: &str) -> (MatterFormat, String, String) {
+    if let Some(rest) = source.strip_prefix("+++") {
+        if let Some(end) = rest.find("\n+++") {
+            let body = &rest[end + 4..];
+            let body = body.strip_prefix('\n').unwrap_or(body);
+            return (
+                MatterFormat::Toml,
+                rest[..end].to_string(),
+                body.to_string(),
+            );
+        }
+    }
+    let (matter, content) =
+        frontmatter::split_matter(source).unwrap_or((String::new(), source.to_string()));
+    (MatterFormat::Yaml, matter, content)
+}
+
+fn parse_frontmatter(
+    format: MatterFormat,
+    matter: &str,
+) -> Result<FrontMatter, (String, Option<usize>)> {
+    if matter.trim().is_empty() {
+        return Ok(FrontMatter::default());
+    }
+    match format {
+        MatterFormat::Yaml => serde_yaml::from_str(matter)
+            .map_err(|e| (e.to_string(), e.location().map(|l| l.index()))),
+        MatterFormat::Toml => toml::from_str(matter).map_err(|e| {
+            let offset = e.line_col().map(|(line, column)| {
+                matter
+                    .split_inclusive('\n')
+                    .take(line)
+                    .map(str::len)
+                    .sum::<usize>()
+                    + column
+            });
+            (e.to_string(), offset)
+        }),
+    }
 }
 
 impl Post {
-    pub fn new(filename: SourcePath) -> Result<Self, anyhow::Error> {
-        let mut file = File::open(filename.0.as_path())?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
-        let (matter, content) =
-            frontmatter::split_matter(&content).unwrap_or((String::new(), content.clone()));
-        let matter = serde_yaml::from_str(&matter)?;
-        Ok(Self {
-            frontmatter: matter,
+    pub fn new(filename: SourcePath) -> Result<Self, Diagnostic> {
+        let source = fs::read_to_string(filename.0.as_path())
+            .map_err(|e| Diagnostic::new(filename.0.clone(), e))?;
+        let hash = content_hash(&source);
+        let (format, matter, content) = split_frontmatter(&source);
+        let mut frontmatter = parse_frontmatter(format, &matter).map_err(|(message, offset)| {
+            // Parsers report positions within the frontmatter block, so
+            // shift them to where that block starts in the file.
+            let message = message.split(" at line ").next().unwrap_or(&message);
+            let diagnostic = Diagnostic::new(filename.0.clone(), message);
+            match offset {
+                Some(offset) => {
+                    let start = source.find(matter.as_str()).unwrap_or(0);
+                    diagnostic.at(&source, start + offset)
+                }
+                None => diagnostic,
+            }
+        })?;
+        if frontmatter.title.is_empty() {
+            frontmatter.title = filename.name().replace('-', " ");
+        }
+        let committed = frontmatter.date.or_else(|| commit_date(&filename.0));
+        let date = match committed.or_else(|| modified_date(&filename.0)) {
+            Some(date) => date,
+            None => return Err(Diagnostic::new(
+                filename.0.clone(),
+                "no `date` in the frontmatter, and no commit or modification time to date it by",
+            )),
+        };
+        let written_tags = frontmatter.tags.clone();
+        let mut post = Self {
+            frontmatter,
             content,
             filename,
-        })
+            hash,
+            date,
+            tag_names: BTreeMap::new(),
+            written_tags,
+            linked: BTreeMap::new(),
+        };
+        // Pages aren't listed by date, so only posts are worth a mention
+        if committed.is_none() && !post.is_page() {
+            log::warn!(
+                "{} has no `date` and isn't committed to git, so it is dated by when it was last modified",
+                post.filename.0.display()
+            );
+        }
+        // Relative to the post, so they mean the same on every page that
+        // shows them
+        let image = post
+            .frontmatter
+            .image
+            .as_deref()
+            .map(|src| post.resolve(src));
+        let gallery = post
+            .frontmatter
+            .gallery
+            .iter()
+            .map(|image| GalleryImage {
+                src: post.resolve(&image.src),
+                ..image.clone()
+            })
+            .collect();
+        post.frontmatter.image = image;
+        post.frontmatter.gallery = gallery;
+        Ok(post)
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.frontmatter
+            .kind
+            .unwrap_or_else(|| self.filename.default_kind())
+    }
+
+    pub fn is_page(&self) -> bool {
+        self.kind() == Kind::Page
+    }
+
+    pub fn public_path(&self, include_dir: bool) -> PublicPath {
+        self.filename.to_public_path_as(self.kind(), include_dir)
     }
 
-    pub fn as_html(&self) -> Markup {
-        layout(
-            &self.frontmatter.title,
-            html! {
-                h1 { (self.frontmatter.title)}
-                article {
-                    (Markdown(&self.content))
+    /// Where the redirect for each of the post's `aliases` is written, with
+    /// `index.html` added to those ending in `/`.
+    pub fn alias_paths(&self) -> Vec<PathBuf> {
+        self.frontmatter
+            .aliases
+            .iter()
+            .map(|alias| {
+                let mut path = PathBuf::from("public");
+                path.push(alias.trim_start_matches('/'));
+                if alias.ends_with('/') {
+                    path.push("index.html");
                 }
-            },
-        )
+                path
+            })
+            .collect()
+    }
+
+    /// The site path relative links in the post resolve against: its
+    /// bundle's directory for a bundle, otherwise the one its page is in.
+    pub fn base(&self) -> String {
+        let mut base = PathBuf::from("/");
+        base.push(self.public_path(false).0);
+        if self.filename.is_bundle() {
+            base.set_extension("");
+        } else {
+            base.pop();
+        }
+        let base = base.to_string_lossy();
+        if base.ends_with('/') {
+            base.to_string()
+        } else {
+            format!("{}/", base)
+        }
+    }
+
+    /// `dest` as a path from the site root when it is written relative to
+    /// the post, otherwise as written. Links to other Markdown files, like
+    /// `./bashdoc2.md`, point at the pages made from them, or where one
+    /// would be when the file isn't a post.
+    pub fn resolve(&self, dest: &str) -> String {
+        if let Some((source, rest)) = self.linked_source(dest) {
+            let public = match self.linked.get(&source.0) {
+                Some(public) => public.clone(),
+                None => source.to_public_path(false),
+            };
+            return format!("/{}{}", public.0.display(), rest);
+        }
+        links::absolute(&self.base(), dest).unwrap_or_else(|| dest.to_string())
+    }
+
+    /// The content file each relative link to Markdown in the body names.
+    pub fn linked_sources(&self) -> Vec<SourcePath> {
+        Parser::new(&self.content)
+            .filter_map(|event| match event {
+                Event::Start(Tag::Link(_, ref dest, _)) => {
+                    self.linked_source(dest).map(|(source, _)| source)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The content file a relative link to Markdown in the post names, found
+    /// from the directory the post is in, and the query and fragment after
+    /// it. The file may not exist.
+    pub fn linked_source<'d>(&self, dest: &'d str) -> Option<(SourcePath, &'d str)> {
+        if !links::is_relative(dest) {
+            return None;
+        }
+        let (path, rest) = dest.split_at(dest.find(&['?', '#'][..]).unwrap_or(dest.len()));
+        let path = percent_decode_str(path).decode_utf8().ok()?;
+        let path = Path::new(path.as_ref());
+        if path.extension()? != "md" {
+            return None;
+        }
+        let mut source = self.filename.0.parent()?.to_path_buf();
+        for component in path.components() {
+            match component {
+                Component::ParentDir => {
+                    source.pop();
+                }
+                Component::Normal(name) => source.push(name),
+                _ => {}
+            }
+        }
+        Some((SourcePath(source), rest))
+    }
+
+    /// Each of the post's tags, as its slug and how to show it.
+    pub fn tags(&self) -> Vec<(&str, &str)> {
+        self.frontmatter
+            .tags
+            .iter()
+            .map(|slug| {
+                let name = self.tag_names.get(slug).unwrap_or(slug);
+                (slug.as_str(), name.as_str())
+            })
+            .collect()
+    }
+
+    pub fn project_link(&self) -> Option<ProjectLink<'_>> {
+        self.frontmatter.link.as_deref().map(|url| ProjectLink {
+            url,
+            label: self.frontmatter.link_text.as_deref(),
+        })
     }
 }
 
@@ -98,17 +429,49 @@ fn is_md(entry: &DirEntry) -> bool {
     entry.path().extension().map(|s| s == "md").unwrap_or(false)
 }
 
-pub fn find_content(location: &'static str) -> Result<Vec<SourcePath>, anyhow::Error> {
-    Ok(WalkDir::new(location)
+/// Every Markdown file under `location`, except those inside a bundle
+/// other than the bundle's own `index.md`, which are left out with a
+/// warning.
+pub fn find_content(
+    location: &'static str,
+) -> Result<(Vec<SourcePath>, Diagnostics), anyhow::Error> {
+    let files = WalkDir::new(location)
         .into_iter()
         .filter_entry(|e| !is_hidden(e))
         .filter_map(|e| e.ok())
         .filter(|e| is_md(e))
         .map(|entry| SourcePath(entry.into_path()))
-        .collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let bundles = files
+        .iter()
+        .filter_map(SourcePath::bundle_dir)
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    let mut found = Vec::with_capacity(files.len());
+    let mut diagnostics = Diagnostics::default();
+    for file in files.into_iter() {
+        let bundle = bundles
+            .iter()
+            .find(|dir| file.0.starts_with(dir) && file.bundle_dir() != Some(dir));
+        match bundle {
+            Some(dir) => diagnostics.push(
+                Diagnostic::new(
+                    &file.0,
+                    format!(
+                        "not built, as it is inside the bundle {}, which only builds its {}",
+                        dir.display(),
+                        BUNDLE_INDEX
+                    ),
+                )
+                .warning(),
+            ),
+            None => found.push(file),
+        }
+    }
+    Ok((found, diagnostics))
 }
 
-pub fn copy_resources(location: &'static str) -> Result<(), anyhow::Error> {
+pub fn copy_resources(location: &'static str, out: &mut Output) -> Result<(), anyhow::Error> {
     for entry in WalkDir::new(location)
         .into_iter()
         .filter_entry(|e| !is_hidden(e))
@@ -120,25 +483,128 @@ pub fn copy_resources(location: &'static str) -> Result<(), anyhow::Error> {
             let mut old_file = File::open(entry.path())?;
             let mut old_contents = Vec::new();
             old_file.read_to_end(&mut old_contents)?;
-            let mut new_file = File::create(&new_path)?;
-            new_file.write_all(&old_contents)?;
+            out.write(&new_path, &old_contents)?;
         }
     }
     Ok(())
 }
 
+/// Copies everything in `post`'s bundle but Markdown, which isn't built
+/// from a bundle's other files, to the bundle's directory in `public/`,
+/// keeping any folders within it. Files that can't be copied are skipped
+/// and reported.
+pub fn copy_bundle(post: &Post, out: &mut Output) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    let dir = match post.filename.bundle_dir() {
+        Some(dir) => dir,
+        None => return diagnostics,
+    };
+    let base = post.base();
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| !is_hidden(e))
+        .filter_map(|e| e.ok())
+    {
+        if !entry.path().is_file() || is_md(&entry) {
+            continue;
+        }
+        let mut new_path = PathBuf::from("public");
+        new_path.push(base.trim_start_matches('/'));
+        new_path.push(entry.path().strip_prefix(dir).unwrap_or(entry.path()));
+        let copied = fs::read(entry.path())
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| out.write(&new_path, &contents));
+        if let Err(e) = copied {
+            diagnostics.push(Diagnostic::new(
+                entry.path(),
+                format!("couldn't copy into the bundle's output: {}", e),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// `name` lowercased, with every run of characters other than letters and
+/// digits replaced by a single dash, for use in URLs.
+pub fn slugify(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SourcePath(pub PathBuf);
 
 impl SourcePath {
-    pub fn to_public_path(&self, include_dir: bool) -> PublicPath {
-        let mut new_path = if include_dir {
-            PathBuf::from("public/posts")
+    /// Whether this is the `index.md` of a bundle: a directory in a section
+    /// of `content/` holding a post along with the images and files it
+    /// uses.
+    pub fn is_bundle(&self) -> bool {
+        self.bundle_dir().is_some()
+    }
+
+    /// The bundle directory, if this is a bundle's `index.md`. One directly
+    /// in a section, like `content/rust/index.md`, is an ordinary post, so
+    /// the section's other posts aren't taken for the bundle's files.
+    pub fn bundle_dir(&self) -> Option<&Path> {
+        if self.0.file_name()? != BUNDLE_INDEX {
+            return None;
+        }
+        let dir = self.0.parent()?;
+        let section = dir.parent()?;
+        section.parent().filter(|p| !p.as_os_str().is_empty())?;
+        Some(dir)
+    }
+
+    /// What the post is called in URLs: the file's name, or the bundle's.
+    pub fn name(&self) -> String {
+        let name = match self.bundle_dir() {
+            Some(dir) => dir.file_name(),
+            None => self.0.file_stem(),
+        };
+        name.map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The directory the file, or its bundle, is in.
+    fn folder(&self) -> Option<&Path> {
+        self.bundle_dir().unwrap_or(&self.0).parent()
+    }
+
+    /// Files under `content/pages/` are pages unless their frontmatter says
+    /// otherwise.
+    pub fn default_kind(&self) -> Kind {
+        let parent = self.folder().and_then(|p| p.file_name());
+        if parent.map(|p| p == PAGES_DIR).unwrap_or(false) {
+            Kind::Page
         } else {
-            PathBuf::from("posts/")
+            Kind::Post
+        }
+    }
+
+    /// The directory under `content/` the file, or its bundle, is in, if
+    /// any.
+    pub fn section(&self) -> Option<String> {
+        let parent = self.folder()?;
+        parent.parent().filter(|p| !p.as_os_str().is_empty())?;
+        parent.file_name().map(|s| s.to_string_lossy().to_string())
+    }
+
+    pub fn to_public_path(&self, include_dir: bool) -> PublicPath {
+        self.to_public_path_as(self.default_kind(), include_dir)
+    }
+
+    /// Posts live under `posts/`, pages at the top level.
+    pub fn to_public_path_as(&self, kind: Kind, include_dir: bool) -> PublicPath {
+        let mut new_path = match (kind, include_dir) {
+            (Kind::Post, true) => PathBuf::from("public/posts"),
+            (Kind::Post, false) => PathBuf::from("posts/"),
+            (Kind::Page, true) => PathBuf::from("public"),
+            (Kind::Page, false) => PathBuf::new(),
         };
-        new_path.push(self.0.file_name().unwrap());
-        new_path.set_extension("html");
+        new_path.push(format!("{}.html", self.name()));
         PublicPath(new_path)
     }
 }
@@ -178,3 +644,190 @@ impl PublicPath {
         SourcePath(new_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(path: &str, content: &str) -> Post {
+        Post {
+            frontmatter: FrontMatter::default(),
+            content: content.to_string(),
+            filename: SourcePath(PathBuf::from(path)),
+            hash: String::new(),
+            date: DateTime::parse_from_rfc3339("2021-03-04T00:00:00-05:00").unwrap(),
+            tag_names: BTreeMap::new(),
+            written_tags: Vec::new(),
+            linked: BTreeMap::new(),
+        }
+    }
+
+    /// An empty directory of the test's own.
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("portfolio-rs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn slugify_keeps_letters_and_digits() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Web   Dev  "), "web-dev");
+        assert_eq!(slugify("C++ 2020"), "c-2020");
+        assert_eq!(slugify("Crème Brûlée"), "crème-brûlée");
+        assert_eq!(slugify("---"), "");
+    }
+
+    #[test]
+    fn only_an_index_below_a_section_is_a_bundle() {
+        let bundle = SourcePath(PathBuf::from("content/travel/trip/index.md"));
+        assert_eq!(bundle.bundle_dir(), Some(Path::new("content/travel/trip")));
+        assert_eq!(bundle.name(), "trip");
+        assert_eq!(bundle.section().as_deref(), Some("travel"));
+        assert!(!SourcePath(PathBuf::from("content/rust/index.md")).is_bundle());
+        assert!(!SourcePath(PathBuf::from("content/index.md")).is_bundle());
+        assert!(!SourcePath(PathBuf::from("content/travel/trip/notes.md")).is_bundle());
+    }
+
+    #[test]
+    fn linked_source_follows_relative_links_to_markdown() {
+        let post = post("content/rust/bashdoc3.md", "");
+        let linked = |dest: &'static str| {
+            post.linked_source(dest)
+                .map(|(source, rest)| (source.0, rest))
+        };
+        let rust = |name: &str| PathBuf::from("content/rust").join(name);
+        assert_eq!(linked("./bashdoc2.md"), Some((rust("bashdoc2.md"), "")));
+        assert_eq!(
+            linked("bashdoc2.md#usage"),
+            Some((rust("bashdoc2.md"), "#usage"))
+        );
+        assert_eq!(
+            linked("../python/daily-tracker.md?ref=feed#top"),
+            Some((
+                PathBuf::from("content/python/daily-tracker.md"),
+                "?ref=feed#top"
+            ))
+        );
+        assert_eq!(linked("./my%20notes.md"), Some((rust("my notes.md"), "")));
+        assert_eq!(linked("./diagram.png"), None);
+        assert_eq!(linked("/posts/bashdoc2.md"), None);
+        assert_eq!(linked("https://example.com/README.md"), None);
+        assert_eq!(linked("#bashdoc2.md"), None);
+    }
+
+    #[test]
+    fn linked_source_is_relative_to_a_bundle_s_directory() {
+        let post = post("content/travel/trip/index.md", "");
+        let linked = |dest: &'static str| {
+            post.linked_source(dest)
+                .map(|(source, rest)| (source.0, rest))
+        };
+        assert_eq!(
+            linked("../packing.md"),
+            Some((PathBuf::from("content/travel/packing.md"), ""))
+        );
+        assert_eq!(
+            linked("day-2/index.md#morning"),
+            Some((
+                PathBuf::from("content/travel/trip/day-2/index.md"),
+                "#morning"
+            ))
+        );
+        assert_eq!(
+            linked("../../rust/bashdoc.md"),
+            Some((PathBuf::from("content/rust/bashdoc.md"), ""))
+        );
+    }
+
+    #[test]
+    fn resolve_points_markdown_links_where_the_target_is_published() {
+        let bundle = post("content/travel/trip/index.md", "");
+        assert_eq!(bundle.resolve("./photo.png"), "/posts/trip/photo.png");
+        assert_eq!(bundle.resolve("../packing.md"), "/posts/packing.html");
+
+        let mut post = post("content/rust/bashdoc3.md", "");
+        assert_eq!(
+            post.resolve("./bashdoc2.md#usage"),
+            "/posts/bashdoc2.html#usage"
+        );
+        post.linked.insert(
+            PathBuf::from("content/rust/bashdoc2.md"),
+            PublicPath(PathBuf::from("bashdoc2.html")),
+        );
+        assert_eq!(post.resolve("./bashdoc2.md#usage"), "/bashdoc2.html#usage");
+        assert_eq!(post.resolve("./diagram.png"), "/posts/diagram.png");
+        assert_eq!(post.resolve("https://example.com/"), "https://example.com/");
+    }
+
+    #[test]
+    fn copy_bundle_copies_everything_but_markdown() {
+        let bundle = scratch("bundle").join("content/travel/trip");
+        fs::create_dir_all(bundle.join("day-2")).unwrap();
+        fs::create_dir_all(bundle.join(".drafts")).unwrap();
+        for file in [
+            "index.md",
+            "notes.md",
+            "photo.png",
+            "day-2/index.md",
+            "day-2/map.svg",
+            ".drafts/photo.png",
+        ] {
+            fs::write(bundle.join(file), file).unwrap();
+        }
+        let post = post(bundle.join("index.md").to_str().unwrap(), "");
+        let mut out = Output::new("public", ".cache", true);
+        assert!(copy_bundle(&post, &mut out).is_empty());
+        assert_eq!(
+            out.produced().iter().collect::<Vec<_>>(),
+            vec![
+                Path::new("public/posts/trip/day-2/map.svg"),
+                Path::new("public/posts/trip/photo.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn copy_bundle_does_nothing_for_other_posts() {
+        let post = post("content/rust/bashdoc.md", "");
+        let mut out = Output::new("public", ".cache", true);
+        assert!(copy_bundle(&post, &mut out).is_empty());
+        assert!(out.produced().is_empty());
+    }
+
+    #[test]
+    fn toml_errors_point_into_the_file() {
+        let file = scratch("toml").join("broken.md");
+        fs::write(&file, "+++\ntitle = \"Broken\"\ndate = nope\n+++\n\nBody\n").unwrap();
+        let error = Post::new(SourcePath(file)).unwrap_err();
+        assert_eq!(error.position, Some((3, 8)));
+        assert_eq!(error.snippet.as_deref(), Some("date = nope"));
+    }
+
+    #[test]
+    fn undated_files_outside_git_are_dated_by_modification() {
+        let dir = scratch("undated").join("pages");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("about.md");
+        fs::write(&file, "---\ntitle: About\n---\n\nHi\n").unwrap();
+        let modified = DateTime::<Utc>::from(fs::metadata(&file).unwrap().modified().unwrap());
+        let page = Post::new(SourcePath(file)).unwrap();
+        assert!(page.is_page());
+        assert_eq!(page.date, modified);
+    }
+
+    #[test]
+    fn aliases_are_written_under_public() {
+        let mut page = post("content/pages/about.md", "");
+        page.frontmatter.aliases = vec![String::from("/posts/about.html"), String::from("/me/")];
+        assert_eq!(
+            page.alias_paths(),
+            vec![
+                PathBuf::from("public/posts/about.html"),
+                PathBuf::from("public/me/index.html"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! Templates loaded at run time, so markup can change without recompiling.
+//!
+//! Enabled with the `runtime-templates` feature and by pointing `templates` in
+//! `config.yaml` at a directory. Every `*.html` file in it is a
+//! [MiniJinja](https://docs.rs/minijinja) template, named by its file name.
+//! Each page uses its template when one exists and the built-in maud markup
+//! otherwise, so a theme can replace as little as a single layout:
+//!
+//! | Page                      | Template                                   |
+//! |---------------------------|--------------------------------------------|
+//! | each post or page         | `<layout>.html`, e.g. `post.html`, `page.html`, `project.html`, `gallery.html` |
+//! | `index.html`              | `index.html`                               |
+//! | `tags/tags.html`, and each taxonomy's index | `tags.html`             |
+//! | `tags/<tag>.html`, and each term's page     | `tag.html`              |
+//! | `series/<series>.html`    | `series.html`                              |
+//!
+//! A template named after a layout also makes that name valid in a post's
+//! `layout` frontmatter.
+//!
+//! # Context
+//!
+//! Every template gets:
+//!
+//! - `site`: the site config: `title`, `description`, `base_url` and `author`.
+//! - `title`: the page title.
+//! - `tags`: every tag's slug, mapped to the posts using it, newest first.
+//!   `tags[slug] | length` is how many posts use it.
+//! - `tag_names`: every tag's slug, mapped to its display name.
+//!
+//! Post templates also get `meta`, what the built-in theme puts in `<head>`
+//! for link previews: the `title`, `site_name`, `description`, canonical
+//! `url` and `image`, all absolute, and for posts an `article` with its
+//! `published` and `modified` times, `author`, `tags` and project `link`,
+//! `breadcrumbs`, each a `name` and `url`, `menu`, the `name` and `url` of
+//! each sidebar link in the site config, `taxonomies`, the `name`, `title`
+//! and site-root `url` of each taxonomy's index, and the site's `tagline`
+//! and the `about` page it links to. `json_ld` is the schema.org
+//! data for the post, as safe text for a
+//! `<script type="application/ld+json">`; `check` validates it.
+//!
+//! Post templates also get `post`, and listing templates get `posts`, a list
+//! of the same shape:
+//!
+//! - `title`, `date` (RFC 3339), `tags`, `layout` and `kind` (`post` or `page`).
+//! - `url`: path from the site root, e.g. `/posts/bashdoc.html`.
+//! - `link`, `link_text`: the project link, when there is one.
+//! - `content`: the rendered body, as safe HTML.
+//! - `gallery`: the grid of the frontmatter `gallery`, as safe HTML, empty
+//!   when there is none.
+//! - `blurb`: the start of the body, as safe HTML.
+//! - `frontmatter`: every frontmatter field, including ones the generator does
+//!   not know about.
+//!
+//! Post templates also get `navigation`, the posts around this one. Each of
+//! `previous` (older) and `next` (newer) is a `title` and `url`, or `none`:
+//!
+//! - `navigation.previous`, `navigation.next`: among every post.
+//! - `navigation.section`: the directory under `content/`, such as `rust`, and
+//!   `navigation.in_section.previous` and `.next` among the posts there.
+//! - `navigation.tags`: each of the post's tags, mapped to its `previous` and
+//!   `next` among the posts with that tag.
+//! - `navigation.related`: the most related posts, best first, each a `title`
+//!   and `url`. How many, and how they are scored, is set under `related` in
+//!   the site config.
+//! - `navigation.series`: for posts in a series, its `name`, the `url` of its
+//!   index page, this post's `part` number and every one of its `parts`, in
+//!   order, each a `title` and `url`.
+//!
+//! Listing templates also get `pagination`: `page` and `pages` (one-based),
+//! `per_page`, `total` and the `previous` and `next` page URLs, which are
+//! `none` at either end.
+//!
+//! `tags.html` and `tag.html` render tags and every taxonomy in the site
+//! config alike, and get `taxonomy`: its `name`, `title`, the `url` of its
+//! index and its `terms`, each term's slug mapped to the term. `tag.html`
+//! also gets `tag`, the term being listed. A term has a `slug`, `url`, the
+//! `feed` of its posts, the `name` and `description` from `tags.yaml`, the
+//! slug of its `parent` when it is nested, and the `count` of its posts.
+//!
+//! `series.html` gets `series`, the series' `name` and `url`, with `posts` in
+//! series order.
+use crate::{
+    cache::{Listed, PostsDatabase},
+    config::Config,
+    meta::Meta,
+    navigation::{Navigation, Series},
+    output::Output,
+    retrieve::{FrontMatter, Kind, Post, SourcePath},
+    tags::Tags,
+    taxonomy::{parent, Taxonomy},
+    templates::{layout::index, pages},
+    theme::Templates,
+};
+use minijinja::{Environment, Value};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    sync::Arc,
+};
+
+/// Templates from the directory named in the site config.
+pub struct Engine {
+    env: Environment<'static>,
+    names: BTreeSet<String>,
+}
+
+impl Engine {
+    /// Loads the templates the config asks for, if any.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, anyhow::Error> {
+        config.templates.as_deref().map(Engine::load).transpose()
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, anyhow::Error> {
+        let mut env = Environment::new();
+        let mut names = BTreeSet::new();
+        let entries = fs::read_dir(dir)
+            .map_err(|e| anyhow::anyhow!("Can't read templates in {}: {}", dir.display(), e))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().map(|e| e != "html").unwrap_or(true) {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            env.add_template_owned(name.clone(), fs::read_to_string(&path)?)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            names.insert(name);
+        }
+        log::debug!("Loaded {} template(s) from {}", names.len(), dir.display());
+        Ok(Engine { env, names })
+    }
+
+    /// Layout names the templates provide, in addition to the built-in ones.
+    pub fn layouts(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .filter_map(|name| name.strip_suffix(".html"))
+            .filter(|name| !matches!(*name, "index" | "tags" | "tag" | "series"))
+    }
+
+    fn render<S: Serialize>(
+        &self,
+        name: &str,
+        context: S,
+    ) -> Result<Option<String>, anyhow::Error> {
+        if !self.names.contains(name) {
+            return Ok(None);
+        }
+        self.env
+            .get_template(name)
+            .and_then(|template| template.render(context))
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{}", display_error(&e)))
+    }
+
+    /// Writes every post, page and listing, using a template where there is
+    /// one and the built-in markup where there isn't.
+    pub fn render_site(
+        &self,
+        db: &PostsDatabase,
+        templates: &Templates,
+        tag_data: &Tags,
+        files: &[SourcePath],
+        posts: &[SourcePath],
+        out: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        let config = db.site_config();
+        let theme = templates.theme();
+        let tag_map = db.get_terms(&Taxonomy::tags(config.tag_cloud), posts);
+        let tags = Value::from_serialize(
+            tag_map
+                .iter()
+                .map(|(tag, posts)| (tag, newest_first(db, posts)))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        let tag_names = Value::from_serialize(
+            tag_map
+                .keys()
+                .map(|tag| (tag, tag_data.name(tag)))
+                .collect::<BTreeMap<_, _>>(),
+        );
+
+        for path in files.iter() {
+            let post = db.post(path);
+            let name = format!("{}.html", Templates::layout_name(&post));
+            let navigation = db.post_navigation(path);
+            let meta = Meta::post(&config, &post, db.card_url(path).as_deref());
+            let context = Context {
+                post: Some(PostContext::new(db, &post)),
+                navigation: Some(&navigation),
+                json_ld: meta.json_ld_script().map(Value::from_safe_string),
+                meta: Some(meta),
+                ..Context::new(&config, &post.frontmatter.title, &tags, &tag_names)
+            };
+            let html = match self.render(&name, context)? {
+                Some(html) => html,
+                None => db.html(path).to_string(),
+            };
+            out.write(&post.public_path(true).0, html.as_bytes())?;
+        }
+
+        let recent = db.five_most_recent(posts);
+        let context = Context {
+            posts: Some(newest_first(db, &recent)),
+            pagination: Some(Pagination::single(recent.len())),
+            ..Context::new(&config, &config.title, &tags, &tag_names)
+        };
+        let html = match self.render("index.html", context)? {
+            Some(html) => html,
+            None => index(theme, &config, &recent).into_string(),
+        };
+        out.write("public/index.html", html.as_bytes())?;
+
+        for taxonomy in config.all_taxonomies() {
+            let names = &db.term_names(&taxonomy, tag_data, posts);
+            let term_map = db.get_terms(&taxonomy, posts);
+            let terms = Value::from_serialize(
+                term_map
+                    .iter()
+                    .map(|(term, posts)| (term, TermContext::new(&taxonomy, names, term, posts)))
+                    .collect::<BTreeMap<_, _>>(),
+            );
+            let info = TaxonomyContext {
+                name: &taxonomy.name,
+                title: taxonomy.title(),
+                url: taxonomy.index_url(),
+                terms,
+            };
+            let context = Context {
+                taxonomy: Some(&info),
+                pagination: Some(Pagination::single(term_map.len())),
+                ..Context::new(&config, &info.title, &tags, &tag_names)
+            };
+            let html = match self.render("tags.html", context)? {
+                Some(html) => html,
+                None => {
+                    pages::index_of_terms(theme, &config, &taxonomy, names, &term_map).into_string()
+                }
+            };
+            out.write(format!("public{}", taxonomy.index_url()), html.as_bytes())?;
+
+            for (term, term_posts) in term_map.iter() {
+                let context = Context {
+                    taxonomy: Some(&info),
+                    tag: Some(TermContext::new(&taxonomy, names, term, term_posts)),
+                    posts: Some(newest_first(db, term_posts)),
+                    pagination: Some(Pagination::single(term_posts.len())),
+                    ..Context::new(&config, names.name(term), &tags, &tag_names)
+                };
+                let html = match self.render("tag.html", context)? {
+                    Some(html) => html,
+                    None => pages::term(theme, &config, &taxonomy, names, term, term_posts)
+                        .into_string(),
+                };
+                out.write(format!("public{}", taxonomy.url(term)), html.as_bytes())?;
+            }
+        }
+
+        for (name, parts) in db.get_series(posts).iter() {
+            let series = SeriesContext {
+                name,
+                url: Series::url(name),
+            };
+            let context = Context {
+                series: Some(series),
+                posts: Some(
+                    parts
+                        .iter()
+                        .map(|post| PostContext::new(db, post))
+                        .collect(),
+                ),
+                pagination: Some(Pagination::single(parts.len())),
+                ..Context::new(&config, name, &tags, &tag_names)
+            };
+            let html = match self.render("series.html", context)? {
+                Some(html) => html,
+                None => pages::series_page(theme, &config, name, parts).into_string(),
+            };
+            out.write(format!("public{}", Series::url(name)), html.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A MiniJinja error, which names the template and line, with its causes.
+fn display_error(error: &minijinja::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
+
+fn newest_first(db: &PostsDatabase, posts: &[Listed]) -> Vec<PostContext> {
+    let mut posts = posts.to_vec();
+    posts.sort_by(|a, b| b.date.cmp(&a.date));
+    posts
+        .iter()
+        .map(|post| PostContext::new(db, post))
+        .collect()
+}
+
+/// What every template is rendered with. See the module docs.
+#[derive(Serialize)]
+struct Context<'a> {
+    site: &'a Config,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_ld: Option<Value>,
+    tags: Value,
+    tag_names: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post: Option<PostContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    navigation: Option<&'a Navigation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    posts: Option<Vec<PostContext>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    taxonomy: Option<&'a TaxonomyContext<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<TermContext<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    series: Option<SeriesContext<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pagination: Option<Pagination>,
+}
+
+#[derive(Serialize)]
+struct TaxonomyContext<'a> {
+    name: &'a str,
+    title: String,
+    url: String,
+    terms: Value,
+}
+
+#[derive(Serialize)]
+struct TermContext<'a> {
+    slug: &'a str,
+    url: String,
+    feed: String,
+    name: &'a str,
+    description: Option<&'a str>,
+    parent: Option<&'a str>,
+    count: usize,
+}
+
+impl<'a> TermContext<'a> {
+    fn new(taxonomy: &Taxonomy, names: &'a Tags, term: &'a str, posts: &[Listed]) -> Self {
+        TermContext {
+            slug: term,
+            url: taxonomy.url(term),
+            feed: taxonomy.feed_url(term),
+            name: names.name(term),
+            description: names.description(term),
+            parent: parent(term),
+            count: posts.len(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SeriesContext<'a> {
+    name: &'a str,
+    url: String,
+}
+
+impl<'a> Context<'a> {
+    fn new(site: &'a Config, title: &'a str, tags: &Value, tag_names: &Value) -> Self {
+        Context {
+            site,
+            title,
+            meta: None,
+            json_ld: None,
+            tags: tags.clone(),
+            tag_names: tag_names.clone(),
+            post: None,
+            navigation: None,
+            posts: None,
+            taxonomy: None,
+            tag: None,
+            series: None,
+            pagination: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PostContext {
+    title: String,
+    url: String,
+    date: String,
+    tags: Vec<String>,
+    layout: String,
+    kind: Kind,
+    link: Option<String>,
+    link_text: Option<String>,
+    content: Value,
+    blurb: Value,
+    gallery: Value,
+    frontmatter: FrontMatter,
+}
+
+impl PostContext {
+    fn new(db: &PostsDatabase, post: &Post) -> Self {
+        let rendered = db.content(&post.filename);
+        PostContext {
+            title: post.frontmatter.title.clone(),
+            url: format!("/{}", post.public_path(false).0.display()),
+            date: post.date.to_rfc3339(),
+            tags: post.frontmatter.tags.clone(),
+            layout: Templates::layout_name(post).to_string(),
+            kind: post.kind(),
+            link: post.frontmatter.link.clone(),
+            link_text: post.frontmatter.link_text.clone(),
+            content: Value::from_safe_string(rendered.content.clone()),
+            blurb: Value::from_safe_string(rendered.blurb.clone()),
+            gallery: Value::from_safe_string(rendered.gallery.clone()),
+            frontmatter: post.frontmatter.clone(),
+        }
+    }
+}
+
+/// Where a listing page sits among the pages of its listing. Listings are
+/// currently always a single page.
+#[derive(Serialize)]
+struct Pagination {
+    page: usize,
+    pages: usize,
+    per_page: usize,
+    total: usize,
+    previous: Option<String>,
+    next: Option<String>,
+}
+
+impl Pagination {
+    fn single(total: usize) -> Self {
+        Pagination {
+            page: 1,
+            pages: 1,
+            per_page: total,
+            total,
+            previous: None,
+            next: None,
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Checking the schema.org JSON-LD in generated pages, whether it came from
+//! the built-in theme or a template.
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use chrono::DateTime;
+use serde_json::Value;
+use std::{collections::BTreeMap, path::PathBuf};
+use url::Url;
+
+/// Types describing a post, which need a name, valid dates and an author.
+const WORKS: &[&str] = &[
+    "Article",
+    "BlogPosting",
+    "CreativeWork",
+    "SoftwareSourceCode",
+];
+
+/// Fields holding a URL, which must be absolute.
+const URL_FIELDS: &[&str] = &[
+    "url",
+    "mainEntityOfPage",
+    "image",
+    "codeRepository",
+    "sameAs",
+];
+
+/// Checks every `<script type="application/ld+json">` in `pages` is JSON
+/// describing what search engines expect.
+pub fn check_structured_data(pages: &BTreeMap<PathBuf, String>) -> Diagnostics {
+    let mut diagnostics = Diagnostics::default();
+    for (path, html) in pages.iter() {
+        for (offset, script) in scripts(html) {
+            let problems = match serde_json::from_str::<Value>(script) {
+                Ok(data) => validate(&data),
+                Err(e) => vec![format!("JSON-LD is not valid JSON: {}", e)],
+            };
+            for problem in problems {
+                diagnostics.push(Diagnostic::new(path.clone(), problem).at(html, offset));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// The byte offset and contents of each JSON-LD script in `html`.
+fn scripts(html: &str) -> Vec<(usize, &str)> {
+    let mut scripts = Vec::new();
+    for (start, _) in html.match_indices("<script") {
+        let rest = &html[start..];
+        let open = match rest.find('>') {
+            Some(open) => open,
+            None => continue,
+        };
+        if !rest[..open].contains("application/ld+json") {
+            continue;
+        }
+        let body = &rest[open + 1..];
+        if let Some(end) = body.find("</script>") {
+            scripts.push((start, &body[..end]));
+        }
+    }
+    scripts
+}
+
+fn validate(data: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    let context = data.get("@context").and_then(Value::as_str);
+    if !context.map(|c| c.contains("schema.org")).unwrap_or(false) {
+        problems.push(String::from("JSON-LD has no schema.org `@context`"));
+    }
+    let nodes = match data.get("@graph") {
+        Some(Value::Array(nodes)) => nodes.iter().collect(),
+        Some(_) => {
+            problems.push(String::from("JSON-LD `@graph` should be a list"));
+            Vec::new()
+        }
+        None => vec![data],
+    };
+    for node in nodes {
+        match node.get("@type").and_then(Value::as_str) {
+            Some(kind) if WORKS.contains(&kind) => validate_work(kind, node, &mut problems),
+            Some("BreadcrumbList") => validate_breadcrumbs(node, &mut problems),
+            Some(_) => {}
+            None => problems.push(String::from("JSON-LD node has no `@type`")),
+        }
+    }
+    problems
+}
+
+fn validate_work(kind: &str, node: &Value, problems: &mut Vec<String>) {
+    if !has_text(node, "headline") && !has_text(node, "name") {
+        problems.push(format!("{} has no `headline` or `name`", kind));
+    }
+    if kind == "BlogPosting" && node.get("datePublished").is_none() {
+        problems.push(format!("{} has no `datePublished`", kind));
+    }
+    for field in ["datePublished", "dateModified"].iter() {
+        if let Some(date) = node.get(*field) {
+            let valid = date
+                .as_str()
+                .map(|date| DateTime::parse_from_rfc3339(date).is_ok())
+                .unwrap_or(false);
+            if !valid {
+                problems.push(format!("{} `{}` is not an RFC 3339 date", kind, field));
+            }
+        }
+    }
+    match node.get("author") {
+        Some(author) if has_text(author, "name") => {}
+        Some(_) => problems.push(format!("{} `author` has no `name`", kind)),
+        None => problems.push(format!("{} has no `author`", kind)),
+    }
+    if kind == "SoftwareSourceCode" && node.get("codeRepository").is_none() {
+        problems.push(format!("{} has no `codeRepository`", kind));
+    }
+    for field in URL_FIELDS.iter() {
+        if let Some(url) = node.get(*field) {
+            if !is_absolute(url) {
+                problems.push(format!("{} `{}` is not an absolute URL", kind, field));
+            }
+        }
+    }
+}
+
+fn validate_breadcrumbs(node: &Value, problems: &mut Vec<String>) {
+    let items = match node.get("itemListElement").and_then(Value::as_array) {
+        Some(items) if !items.is_empty() => items,
+        _ => {
+            problems.push(String::from("BreadcrumbList has no `itemListElement`"));
+            return;
+        }
+    };
+    for (i, item) in items.iter().enumerate() {
+        if item.get("position").and_then(Value::as_u64) != Some(i as u64 + 1) {
+            problems.push(format!(
+                "breadcrumb {} should have `position` {}",
+                i + 1,
+                i + 1
+            ));
+        }
+        if !has_text(item, "name") {
+            problems.push(format!("breadcrumb {} has no `name`", i + 1));
+        }
+        if let Some(url) = item.get("item") {
+            if !is_absolute(url) {
+                problems.push(format!(
+                    "breadcrumb {} `item` is not an absolute URL",
+                    i + 1
+                ));
+            }
+        }
+    }
+}
+
+fn has_text(node: &Value, field: &str) -> bool {
+    node.get(field)
+        .and_then(Value::as_str)
+        .map(|text| !text.trim().is_empty())
+        .unwrap_or(false)
+}
+
+fn is_absolute(url: &Value) -> bool {
+    url.as_str()
+        .map(|url| Url::parse(url).is_ok())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn check(html: &str) -> Vec<String> {
+        let mut pages = BTreeMap::new();
+        pages.insert(PathBuf::from("public/posts/hello.html"), html.to_string());
+        check_structured_data(&pages)
+            .iter()
+            .map(|d| d.message.clone())
+            .collect()
+    }
+
+    fn script(data: &Value) -> String {
+        format!(
+            r#"<html><head><script type="application/ld+json">{}</script><script>let x = "<b>";</script></head></html>"#,
+            data
+        )
+    }
+
+    fn posting() -> Value {
+        json!({
+            "@context": "https://schema.org",
+            "@graph": [
+                {
+                    "@type": "BlogPosting",
+                    "headline": "Hello",
+                    "url": "https://example.com/posts/hello.html",
+                    "datePublished": "2021-03-04T00:00:00-05:00",
+                    "author": { "@type": "Person", "name": "Me" },
+                },
+                {
+                    "@type": "BreadcrumbList",
+                    "itemListElement": [
+                        { "@type": "ListItem", "position": 1, "name": "Home", "item": "https://example.com/" },
+                        { "@type": "ListItem", "position": 2, "name": "Hello" },
+                    ],
+                },
+            ],
+        })
+    }
+
+    #[test]
+    fn valid_data_and_other_scripts_pass() {
+        assert!(check(&script(&posting())).is_empty());
+        assert!(check("<p>No scripts here</p>").is_empty());
+    }
+
+    #[test]
+    fn finds_only_json_ld_scripts() {
+        let html = script(&posting());
+        let found = scripts(&html);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, html.find("<script").unwrap());
+    }
+
+    #[test]
+    fn broken_json_is_reported() {
+        let problems = check(r#"<script type="application/ld+json">{"@context": </script>"#);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("JSON-LD is not valid JSON"));
+    }
+
+    #[test]
+    fn works_need_a_name_dates_and_an_author() {
+        let mut data = posting();
+        let work = &mut data["@graph"][0];
+        work.as_object_mut().unwrap().remove("headline");
+        work.as_object_mut().unwrap().remove("author");
+        work["dateModified"] = json!("yesterday");
+        work["url"] = json!("/posts/hello.html");
+        assert_eq!(
+            check(&script(&data)),
+            vec![
+                "BlogPosting has no `headline` or `name`",
+                "BlogPosting `dateModified` is not an RFC 3339 date",
+                "BlogPosting has no `author`",
+                "BlogPosting `url` is not an absolute URL",
+            ]
+        );
+    }
+
+    #[test]
+    fn source_code_needs_its_repository() {
+        let mut data = posting();
+        data["@graph"][0]["@type"] = json!("SoftwareSourceCode");
+        assert_eq!(
+            check(&script(&data)),
+            vec!["SoftwareSourceCode has no `codeRepository`"]
+        );
+    }
+
+    #[test]
+    fn breadcrumbs_are_numbered_in_order() {
+        let mut data = posting();
+        data["@graph"][1]["itemListElement"][1]["position"] = json!(3);
+        data["@graph"][1]["itemListElement"][0]["item"] = json!("/");
+        assert_eq!(
+            check(&script(&data)),
+            vec![
+                "breadcrumb 1 `item` is not an absolute URL",
+                "breadcrumb 2 should have `position` 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn context_and_types_are_required() {
+        let data = json!({ "@graph": [{ "name": "untyped" }] });
+        assert_eq!(
+            check(&script(&data)),
+            vec![
+                "JSON-LD has no schema.org `@context`",
+                "JSON-LD node has no `@type`",
+            ]
+        );
+    }
+}
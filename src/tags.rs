@@ -0,0 +1,158 @@
+use crate::retrieve::slugify;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, io::ErrorKind};
+
+/// What `tags.yaml` says about one tag.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TagInfo {
+    /// Shown instead of the slug on the tag's pages.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Other spellings filed under this tag.
+    pub aliases: Vec<String>,
+}
+
+/// Display names, descriptions and aliases for tags, keyed by slug.
+#[derive(Debug, Clone, Default)]
+pub struct Tags {
+    info: BTreeMap<String, TagInfo>,
+    aliases: BTreeMap<String, String>,
+}
+
+impl Tags {
+    /// Reads the tag data at `path`, or none when there is no file.
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Tags::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let info: BTreeMap<String, TagInfo> = serde_yaml::from_str(&source)
+            .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path, e))?;
+        Ok(Tags::new(info))
+    }
+
+    pub fn new(info: BTreeMap<String, TagInfo>) -> Self {
+        let info = info
+            .into_iter()
+            .map(|(tag, info)| (slugify(&tag), info))
+            .collect::<BTreeMap<_, _>>();
+        let aliases = info
+            .iter()
+            .flat_map(|(tag, info)| {
+                info.aliases
+                    .iter()
+                    .map(move |alias| (slugify(alias), tag.clone()))
+            })
+            .collect();
+        Tags { info, aliases }
+    }
+
+    /// Tags with only a display name each, keyed by slugs as they are, such
+    /// as the nested terms of a taxonomy other than tags.
+    pub fn named(names: BTreeMap<String, String>) -> Self {
+        let info = names
+            .into_iter()
+            .map(|(slug, name)| {
+                let info = TagInfo {
+                    name: Some(name),
+                    ..TagInfo::default()
+                };
+                (slug, info)
+            })
+            .collect();
+        Tags {
+            info,
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    /// The slug `tag` is filed under, so `Rust`, ` rust ` and any alias of
+    /// `rust` all end up together.
+    pub fn canonical(&self, tag: &str) -> String {
+        let slug = slugify(tag);
+        match self.aliases.get(&slug) {
+            Some(tag) if !self.info.contains_key(&slug) => tag.clone(),
+            _ => slug,
+        }
+    }
+
+    /// Every tag in `tags` made canonical, without duplicates.
+    pub fn canonicalize(&self, tags: &[String]) -> Vec<String> {
+        let mut canonical = Vec::with_capacity(tags.len());
+        for tag in tags.iter().map(|tag| self.canonical(tag)) {
+            if !tag.is_empty() && !canonical.contains(&tag) {
+                canonical.push(tag);
+            }
+        }
+        canonical
+    }
+
+    /// How to show the tag `slug`. Nested terms without a name are shown by
+    /// their last part.
+    pub fn name<'a>(&'a self, slug: &'a str) -> &'a str {
+        self.info
+            .get(slug)
+            .and_then(|info| info.name.as_deref())
+            .unwrap_or_else(|| slug.rsplit('/').next().unwrap_or(slug))
+    }
+
+    pub fn description(&self, slug: &str) -> Option<&str> {
+        self.info
+            .get(slug)
+            .and_then(|info| info.description.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags() -> Tags {
+        let mut info = BTreeMap::new();
+        info.insert(
+            "Rust".to_string(),
+            TagInfo {
+                name: Some("Rust".to_string()),
+                aliases: vec!["rustlang".to_string(), "Rust Lang".to_string()],
+                ..TagInfo::default()
+            },
+        );
+        info.insert(
+            "web-dev".to_string(),
+            TagInfo {
+                name: Some("Web Development".to_string()),
+                ..TagInfo::default()
+            },
+        );
+        Tags::new(info)
+    }
+
+    #[test]
+    fn tags_are_filed_under_their_canonical_slug() {
+        let tags = tags();
+        assert_eq!(tags.canonical("RustLang"), "rust");
+        assert_eq!(tags.canonical(" rust lang "), "rust");
+        assert_eq!(tags.canonical("Web Dev"), "web-dev");
+        assert_eq!(tags.canonical("Python"), "python");
+        assert_eq!(
+            tags.canonicalize(&[
+                "Rust".to_string(),
+                "rustlang".to_string(),
+                "Web Dev".to_string(),
+                "!!".to_string(),
+                "web-dev".to_string(),
+            ]),
+            vec!["rust", "web-dev"]
+        );
+    }
+
+    #[test]
+    fn names_fall_back_to_the_slug() {
+        let tags = tags();
+        assert_eq!(tags.name("web-dev"), "Web Development");
+        assert_eq!(tags.name("python"), "python");
+        assert_eq!(tags.name("languages/go"), "go");
+    }
+}
@@ -0,0 +1,159 @@
+//! Ways of grouping posts. Tags are built in, and `config.yaml` can declare
+//! more, such as `categories`, `languages` or `tools`, whose terms each post
+//! lists in the frontmatter field of the same name:
+//!
+//! ```yaml
+//! languages: [rust/async, python]
+//! ```
+//!
+//! Terms nest with `/`, so a post filed under `rust/async` is listed under
+//! `rust` as well.
+use crate::retrieve::{slugify, Post};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+/// Directories under `public/` a taxonomy can't be written to.
+const RESERVED: &[&str] = &["posts", "pages", "series", "resources", "images", "cards"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Taxonomy {
+    /// The frontmatter field its terms are read from, and the directory
+    /// under `public/` its pages are written to.
+    pub name: String,
+    /// Heading for its pages. Defaults to the name, capitalised.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Size each term on the index page by how many posts use it.
+    #[serde(default)]
+    pub cloud: bool,
+}
+
+impl Taxonomy {
+    pub fn tags(cloud: bool) -> Self {
+        Taxonomy {
+            name: String::from("tags"),
+            title: Some(String::from("Tags")),
+            cloud,
+        }
+    }
+
+    pub fn is_tags(&self) -> bool {
+        self.name == "tags"
+    }
+
+    pub fn title(&self) -> String {
+        match self.title {
+            Some(ref title) => title.clone(),
+            None => {
+                let mut chars = self.name.chars();
+                chars
+                    .next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Why `name` can't be used for a declared taxonomy, if it can't.
+    pub(crate) fn validate(&self) -> Result<(), anyhow::Error> {
+        if slugify(&self.name) != self.name {
+            anyhow::bail!(
+                "taxonomy `{}` needs a lowercase name of letters, digits and `-`",
+                self.name
+            );
+        }
+        if self.is_tags() || RESERVED.contains(&self.name.as_str()) {
+            anyhow::bail!("taxonomy `{}` would overwrite built-in pages", self.name);
+        }
+        Ok(())
+    }
+
+    /// The page listing every term.
+    pub fn index_url(&self) -> String {
+        format!("/{0}/{0}.html", self.name)
+    }
+
+    pub fn url(&self, term: &str) -> String {
+        format!("/{}/{}.html", self.name, term)
+    }
+
+    pub fn feed_url(&self, term: &str) -> String {
+        format!("/{}/{}.xml", self.name, term)
+    }
+
+    /// The terms `post` is filed under as slugs, with the parents of nested
+    /// ones.
+    pub fn terms(&self, post: &Post) -> Vec<String> {
+        let mut terms = Vec::new();
+        for term in self.written(post).unwrap_or_default() {
+            let slug = term_slug(&term);
+            let parts = slug.split('/').filter(|part| !part.is_empty());
+            let parts = parts.collect::<Vec<_>>();
+            for depth in 1..=parts.len() {
+                let ancestor = parts[..depth].join("/");
+                if !terms.contains(&ancestor) {
+                    terms.push(ancestor);
+                }
+            }
+        }
+        terms
+    }
+
+    /// The terms in `post`'s frontmatter as written, or what is wrong with
+    /// the field.
+    pub(crate) fn written(&self, post: &Post) -> Result<Vec<String>, String> {
+        if self.is_tags() {
+            return Ok(post.frontmatter.tags.clone());
+        }
+        match post.frontmatter.extra.get(&self.name) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::String(term)) => Ok(vec![term.clone()]),
+            Some(Value::Sequence(terms)) => terms
+                .iter()
+                .map(|term| match term {
+                    Value::String(term) => Ok(term.clone()),
+                    _ => Err(format!("`{}` should only list text", self.name)),
+                })
+                .collect(),
+            Some(_) => Err(format!(
+                "`{}` should be a term or a list of terms",
+                self.name
+            )),
+        }
+    }
+}
+
+/// `term` made into a URL path: each `/`-separated part slugified, with
+/// empty parts left out.
+pub fn term_slug(term: &str) -> String {
+    term.split('/')
+        .map(slugify)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The term `term` is nested under, if any.
+pub fn parent(term: &str) -> Option<&str> {
+    term.rfind('/').map(|i| &term[..i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn term_slugs_keep_each_level_of_a_hierarchy() {
+        assert_eq!(term_slug("Web Dev"), "web-dev");
+        assert_eq!(term_slug("Languages/Rust Lang"), "languages/rust-lang");
+        assert_eq!(term_slug(" Languages / C++ /"), "languages/c");
+        assert_eq!(term_slug("//"), "");
+        assert_eq!(term_slug("!!!"), "");
+    }
+
+    #[test]
+    fn parent_is_the_term_one_level_up() {
+        assert_eq!(parent("languages/rust/async"), Some("languages/rust"));
+        assert_eq!(parent("rust"), None);
+    }
+}
@@ -1,43 +1,160 @@
 pub mod markdown {
+    use super::gallery::grid;
+    use super::project::ProjectLink;
+    use crate::{
+        cloudinary,
+        config::{Captions, CloudinaryConfig},
+        gallery,
+        images::{srcset, Picture, Pictures, SIZES},
+        retrieve::Post,
+    };
     use maud::{html, Markup, PreEscaped, Render};
     use pulldown_cmark::{html as c_html, CodeBlockKind, CowStr, Event, Parser, Tag};
     use syntect::highlighting::ThemeSet;
     use syntect::html::highlighted_html_for_string;
-    use syntect::parsing::SyntaxSet;
+    use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+    lazy_static::lazy_static! {
+        static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+        static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+    }
 
     /// Renders a block of Markdown using `pulldown-cmark`.
     pub struct Markdown<T: AsRef<str>>(pub T);
 
     impl<T: AsRef<str>> Render for Markdown<T> {
+        fn render(&self) -> Markup {
+            Content {
+                markdown: self.0.as_ref(),
+                post: None,
+                pictures: &Pictures::new(),
+                cloudinary: None,
+                captions: Captions::default(),
+            }
+            .render()
+        }
+    }
+
+    /// Renders a post's Markdown, showing images that have responsive
+    /// versions in `pictures`, or on Cloudinary, at a choice of sizes, and
+    /// images with captions as figures.
+    pub struct Content<'a> {
+        pub markdown: &'a str,
+        /// The post the Markdown is from, which its relative links and
+        /// images are rewritten against, so they work wherever the content
+        /// is shown.
+        pub post: Option<&'a Post>,
+        /// By the `src` of each image, resolved against `post`.
+        pub pictures: &'a Pictures,
+        pub cloudinary: Option<&'a CloudinaryConfig>,
+        pub captions: Captions,
+    }
+
+    impl<'a> Content<'a> {
+        fn resolve<'s>(&self, dest: CowStr<'s>) -> CowStr<'s> {
+            match self.post {
+                Some(post) => CowStr::Boxed(Box::from(post.resolve(&dest))),
+                None => dest,
+            }
+        }
+
+        fn sizing(&self, src: &str) -> Option<Sizing<'a>> {
+            if let Some(picture) = self.pictures.get(src) {
+                return Some(Sizing::Local(picture));
+            }
+            self.cloudinary
+                .and_then(|config| cloudinary::versions(config, src))
+                .map(Sizing::Remote)
+        }
+    }
+
+    impl<'a> Render for Content<'a> {
         fn render(&self) -> Markup {
             // Generate raw HTML
             let mut unsafe_html = String::new();
             let mut lang = String::new();
             let mut in_code_block = false;
-            let parser = Parser::new(self.0.as_ref()).map(|event| match event {
+            // The image whose alt text is being read
+            let mut in_image: Option<Image> = None;
+            // The body of the `gallery` block being read
+            let mut in_gallery: Option<String> = None;
+            let events = figures(Parser::new(self.markdown).collect(), self.captions);
+            let parser = events.into_iter().filter_map(|event| match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref attr)))
+                    if attr.as_ref() == gallery::FENCE =>
+                {
+                    in_gallery = Some(String::new());
+                    None
+                }
+                Event::Text(ref text) if in_gallery.is_some() => {
+                    if let Some(ref mut body) = in_gallery {
+                        body.push_str(text);
+                    }
+                    None
+                }
+                Event::End(Tag::CodeBlock(_)) if in_gallery.is_some() => {
+                    let mut images = gallery::parse_block(&in_gallery.take().unwrap());
+                    for image in images.iter_mut() {
+                        let src = CowStr::from(image.src.clone());
+                        image.src = self.resolve(src).to_string();
+                    }
+                    let markup = grid(&images, self.cloudinary).into_string();
+                    Some(Event::Html(CowStr::Boxed(Box::from(markup))))
+                }
                 Event::Start(Tag::CodeBlock(kind)) => {
                     if let CodeBlockKind::Fenced(ref attr) = kind {
                         lang = attr.to_string();
                         in_code_block = true;
                     }
-                    Event::Start(Tag::CodeBlock(kind))
+                    Some(Event::Start(Tag::CodeBlock(kind)))
                 }
                 Event::Text(ref text) | Event::Html(ref text) if in_code_block => {
-                    Event::Html(CowStr::Boxed(Box::from(highlighted_html_for_language(
-                        &text.to_string(),
-                        lang.clone(),
+                    Some(Event::Html(CowStr::Boxed(Box::from(
+                        highlighted_html_for_language(&text.to_string(), lang.clone()),
                     ))))
                 }
                 Event::End(Tag::CodeBlock(kind)) => {
                     in_code_block = false;
-                    Event::End(Tag::CodeBlock(kind))
+                    Some(Event::End(Tag::CodeBlock(kind)))
+                }
+                Event::Start(Tag::Link(link_type, dest, title)) => {
+                    let dest = self.resolve(dest);
+                    Some(Event::Start(Tag::Link(link_type, dest, title)))
+                }
+                Event::Start(Tag::Image(link_type, src, title)) => {
+                    let src = self.resolve(src);
+                    match self.sizing(&src) {
+                        Some(sizing) => {
+                            in_image = Some(Image {
+                                src: src.to_string(),
+                                title: title.to_string(),
+                                alt: String::new(),
+                                sizing,
+                            });
+                            None
+                        }
+                        None => Some(Event::Start(Tag::Image(link_type, src, title))),
+                    }
+                }
+                Event::Text(ref text) | Event::Code(ref text) if in_image.is_some() => {
+                    if let Some(ref mut image) = in_image {
+                        image.alt.push_str(text);
+                    }
+                    None
                 }
-                _ => event,
+                Event::End(Tag::Image(..)) if in_image.is_some() => {
+                    let markup = in_image.take().unwrap().render().into_string();
+                    Some(Event::Html(CowStr::Boxed(Box::from(markup))))
+                }
+                _ => Some(event),
             });
             c_html::push_html(&mut unsafe_html, parser);
             // Sanitize it with ammonia
             let safe_html = ammonia::Builder::default()
                 .add_generic_attributes(&["class", "style"])
+                .add_tags(&["picture", "source"])
+                .add_tag_attributes("img", &["srcset", "sizes", "loading", "decoding"])
+                .add_tag_attributes("source", &["srcset", "sizes", "type"])
                 .clean(&unsafe_html)
                 .to_string();
             // let safe_html = ammonia::clean(&unsafe_html);
@@ -45,16 +162,181 @@ pub mod markdown {
         }
     }
 
-    fn highlighted_html_for_language(snippet: &str, attributes: String) -> String {
-        lazy_static::lazy_static! {
-            static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
-            static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+    /// Wraps each paragraph holding nothing but an image in a `<figure>`
+    /// when the image has a title or a caption written under it, which
+    /// becomes the `<figcaption>`.
+    fn figures(events: Vec<Event>, captions: Captions) -> Vec<Event> {
+        let mut figures = Vec::with_capacity(events.len());
+        let mut i = 0;
+        while i < events.len() {
+            let image_end = match lone_image(&events, i) {
+                Some(end) => end,
+                None => {
+                    figures.push(events[i].clone());
+                    i += 1;
+                    continue;
+                }
+            };
+            let (link_type, src, title) = match events[i + 1] {
+                Event::Start(Tag::Image(link_type, ref src, ref title)) => {
+                    (link_type, src.clone(), title.clone())
+                }
+                _ => unreachable!(),
+            };
+            // Past the image's paragraph
+            let next = image_end + 2;
+            let (caption, title, next) = match caption(&events, next, captions) {
+                Some((caption, after)) => (caption.to_vec(), title, after),
+                None if !title.is_empty() => (vec![Event::Text(title)], CowStr::from(""), next),
+                None => {
+                    figures.push(events[i].clone());
+                    i += 1;
+                    continue;
+                }
+            };
+            figures.push(Event::Html(CowStr::from("<figure>")));
+            figures.push(Event::Start(Tag::Image(link_type, src, title)));
+            figures.extend(events[i + 2..=image_end].iter().cloned());
+            figures.push(Event::Html(CowStr::from("<figcaption>")));
+            figures.extend(caption);
+            figures.push(Event::Html(CowStr::from("</figcaption></figure>\n")));
+            i = next;
+        }
+        figures
+    }
+
+    /// Where the image ends, if a paragraph holding only an image starts at
+    /// `start`.
+    fn lone_image(events: &[Event], start: usize) -> Option<usize> {
+        match (events.get(start), events.get(start + 1)) {
+            (Some(Event::Start(Tag::Paragraph)), Some(Event::Start(Tag::Image(..)))) => {}
+            _ => return None,
+        }
+        let end = start
+            + 1
+            + events[start + 1..]
+                .iter()
+                .position(|event| matches!(event, Event::End(Tag::Image(..))))?;
+        match events.get(end + 1) {
+            Some(Event::End(Tag::Paragraph)) => Some(end),
+            _ => None,
+        }
+    }
+
+    /// The contents of the caption starting at `start`, written the way
+    /// `captions` says, and where it ends.
+    fn caption<'e, 'a>(
+        events: &'e [Event<'a>],
+        start: usize,
+        captions: Captions,
+    ) -> Option<(&'e [Event<'a>], usize)> {
+        let (open, close) = match captions {
+            Captions::None => return None,
+            Captions::Blockquote => (Tag::BlockQuote, Tag::Paragraph),
+            Captions::Emphasis => (Tag::Paragraph, Tag::Emphasis),
         };
+        // `> caption` is a quote around a paragraph, and `*caption*` a
+        // paragraph around emphasis, so either is four events around the
+        // caption itself
+        let wrapped = events.get(start) == Some(&Event::Start(open.clone()))
+            && events.get(start + 1) == Some(&Event::Start(close.clone()));
+        if !wrapped {
+            return None;
+        }
+        let mut depth = 0;
+        for (i, event) in events.iter().enumerate().skip(start + 2) {
+            match event {
+                Event::Start(tag) if *tag == close => depth += 1,
+                Event::End(tag) if *tag == close && depth > 0 => depth -= 1,
+                Event::End(tag) if *tag == close => {
+                    return match events.get(i + 1) {
+                        Some(Event::End(tag)) if *tag == open => {
+                            Some((&events[start + 2..i], i + 2))
+                        }
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Where the sizes an image is offered at come from.
+    enum Sizing<'a> {
+        /// Versions made from a file on this site.
+        Local(&'a Picture),
+        /// URLs for each width, worked out from the one written.
+        Remote(Vec<(String, u32)>),
+    }
+
+    /// An image from the Markdown, offered at a choice of sizes.
+    struct Image<'a> {
+        src: String,
+        title: String,
+        alt: String,
+        sizing: Sizing<'a>,
+    }
+
+    impl<'a> Render for Image<'a> {
+        fn render(&self) -> Markup {
+            let (src, title, alt) = (&self.src, &self.title, &self.alt);
+            match self.sizing {
+                Sizing::Local(picture) => {
+                    let (fallback, sources) = match picture.sources.split_last() {
+                        Some(split) => split,
+                        None => return html! { img src=(src) alt=(alt) {} },
+                    };
+                    // Sized so the page doesn't jump as it loads
+                    html! {
+                        picture {
+                            @for source in sources {
+                                source type=(source.mime) srcset=(source.srcset()) sizes=(SIZES) {}
+                            }
+                            @if title.is_empty() {
+                                img src=(src) srcset=(fallback.srcset()) sizes=(SIZES) alt=(alt)
+                                    width=(picture.width) height=(picture.height) loading="lazy" decoding="async" {}
+                            } @else {
+                                img src=(src) srcset=(fallback.srcset()) sizes=(SIZES) alt=(alt) title=(title)
+                                    width=(picture.width) height=(picture.height) loading="lazy" decoding="async" {}
+                            }
+                        }
+                    }
+                }
+                Sizing::Remote(ref versions) => {
+                    // The widest, for browsers that don't read `srcset`
+                    let fallback = versions.last().map(|(url, _)| url).unwrap_or(src);
+                    html! {
+                        @if title.is_empty() {
+                            img src=(fallback) srcset=(srcset(versions)) sizes=(SIZES) alt=(alt)
+                                loading="lazy" decoding="async" {}
+                        } @else {
+                            img src=(fallback) srcset=(srcset(versions)) sizes=(SIZES) alt=(alt) title=(title)
+                                loading="lazy" decoding="async" {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn find_syntax(attributes: &str) -> Option<&'static SyntaxReference> {
+        SYNTAX_SET
+            .find_syntax_by_token(attributes)
+            .or_else(|| SYNTAX_SET.find_syntax_by_name(attributes))
+    }
+
+    /// Whether a fenced code block's info string maps to a known syntax.
+    /// Blocks without one are plain text on purpose, and `gallery` blocks
+    /// aren't code at all.
+    pub fn is_known_language(attributes: &str) -> bool {
+        attributes.is_empty() || attributes == gallery::FENCE || find_syntax(attributes).is_some()
+    }
+
+    fn highlighted_html_for_language(snippet: &str, attributes: String) -> String {
         let theme = &THEME_SET.themes["base16-ocean.dark"];
-        let syntax = SYNTAX_SET
-            .find_syntax_by_token(&attributes)
-            .or_else(|| SYNTAX_SET.find_syntax_by_name(&attributes))
-            .unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
+        let syntax =
+            find_syntax(&attributes).unwrap_or_else(|| SYNTAX_SET.find_syntax_plain_text());
         highlighted_html_for_string(snippet, &SYNTAX_SET, syntax, theme)
     }
 
@@ -79,15 +361,22 @@ pub mod markdown {
         }
     }
 
-    pub fn preview(title: &str, tags: Option<&[String]>, blurb: Markup, url: &str) -> Markup {
+    pub fn preview(
+        title: &str,
+        tags: Option<&[(&str, &str)]>,
+        blurb: Markup,
+        url: &str,
+        project: Option<ProjectLink>,
+        thumbnails: Markup,
+    ) -> Markup {
         html! {
             div.card {
                 div.topline {
                     ul.tags {
                         @if let Some(tags) = tags {
-                            @for tag in tags {
-                                a href={"/tags/" (tag) ".html"} {
-                                    li { (tag)}
+                            @for (slug, name) in tags {
+                                li {
+                                    a href={"/tags/" (slug) ".html"} { (name) }
                                 }
                             }
                         }
@@ -98,6 +387,119 @@ pub mod markdown {
                 }
                 a href={"/" (url)} {
                     (blurb)
+                    (thumbnails)
+                }
+                @if let Some(project) = project {
+                    (project)
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn render(markdown: &str, captions: Captions) -> String {
+            Content {
+                markdown,
+                post: None,
+                pictures: &Pictures::new(),
+                cloudinary: None,
+                captions,
+            }
+            .render()
+            .into_string()
+        }
+
+        #[test]
+        fn quotes_under_images_are_captions() {
+            let html = render(
+                "![A cat](/cat.png)\n\n> A *very* `sleepy` cat\n",
+                Captions::Blockquote,
+            );
+            assert!(html.starts_with("<figure><img src=\"/cat.png\" alt=\"A cat\""));
+            assert!(html.contains(
+                "<figcaption>A <em>very</em> <code>sleepy</code> cat</figcaption></figure>"
+            ));
+            assert!(!html.contains("<blockquote>"));
+        }
+
+        #[test]
+        fn captions_can_hold_links_and_nested_emphasis() {
+            let html = render(
+                "![A cat](/cat.png)\n\n*An _odd_ cat, from [the shelter](https://example.com)*\n",
+                Captions::Emphasis,
+            );
+            assert!(html
+                .contains("<figcaption>An <em>odd</em> cat, from <a href=\"https://example.com\""));
+            assert!(html.ends_with("the shelter</a></figcaption></figure>\n"));
+        }
+
+        #[test]
+        fn partly_emphasised_paragraphs_are_not_captions() {
+            let html = render("![A cat](/cat.png)\n\n*A* cat\n", Captions::Emphasis);
+            assert!(!html.contains("<figure>"));
+            assert!(html.contains("<p><em>A</em> cat</p>"));
+        }
+
+        #[test]
+        fn titles_are_captions_when_nothing_else_is() {
+            let html = render(
+                "![A cat](/cat.png \"Asleep\")\n\n> A quote\n",
+                Captions::None,
+            );
+            assert!(html.contains("<figcaption>Asleep</figcaption></figure>"));
+            assert!(!html.contains("title="));
+            assert!(html.contains("<blockquote>"));
+        }
+
+        #[test]
+        fn images_among_text_are_not_figures() {
+            let html = render(
+                "Look: ![A cat](/cat.png)\n\n> A quote\n",
+                Captions::Blockquote,
+            );
+            assert!(!html.contains("<figure>"));
+        }
+    }
+}
+
+pub mod project {
+    use maud::{html, Markup, Render};
+    use url::Url;
+
+    /// A call-to-action button for a post's `link`, labelled with its
+    /// `linkText` or a default chosen by where the link points.
+    pub struct ProjectLink<'a> {
+        pub url: &'a str,
+        pub label: Option<&'a str>,
+    }
+
+    impl<'a> ProjectLink<'a> {
+        /// Icon and fallback label for the link's host.
+        fn kind(&self) -> (&'static str, &'static str) {
+            let host = Url::parse(self.url)
+                .ok()
+                .and_then(|url| url.host_str().map(String::from))
+                .unwrap_or_default();
+            if host == "github.com" || host.ends_with(".github.com") {
+                ("/github.svg", "View on GitHub")
+            } else if host == "res.cloudinary.com" {
+                ("/download.svg", "Download")
+            } else {
+                ("/external-link.svg", "Visit project")
+            }
+        }
+    }
+
+    impl<'a> Render for ProjectLink<'a> {
+        fn render(&self) -> Markup {
+            let (icon, default_label) = self.kind();
+            html! {
+                a.project-link href=(self.url) rel="noopener noreferrer" target="_blank" {
+                    img src=(icon) alt="" {}
+                    span { (self.label.unwrap_or(default_label)) }
                 }
             }
         }
@@ -120,84 +522,344 @@ pub mod css {
 }
 
 pub mod layout {
-    use std::sync::Arc;
-
-    use crate::retrieve::Post;
-
-    use super::{
-        css::Css,
-        markdown::{preview, Blurb},
+    use crate::{
+        cache::Listed,
+        config::Config,
+        meta::Meta,
+        navigation::{Navigation, Neighbours},
+        persist::Rendered,
+        retrieve::Post,
+        theme::Theme,
     };
-    use maud::{html, Markup, DOCTYPE};
 
-    pub fn index(five_recent: &[Arc<Post>]) -> Markup {
+    use super::{css::Css, gallery::thumbnails, markdown::preview};
+    use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+    pub fn index(theme: &dyn Theme, config: &Config, five_recent: &[Listed]) -> Markup {
         layout(
-            "Dustin Knopoff",
+            theme,
+            &Meta::page(config, &config.title, "/"),
             html! {
                 @for post in five_recent {
-                   ( preview(&post.frontmatter.title, Some(&post.frontmatter.tags), html! {
-                       (Blurb(&post.content))
-                   }, post.filename.to_public_path(false).0.to_str().unwrap()))
+                   ( preview(&post.frontmatter.title, Some(&post.tags()), html! {
+                       (PreEscaped(&post.rendered.blurb))
+                   }, post.public_path(false).0.to_str().unwrap(), post.project_link(),
+                   thumbnails(config, post)))
+                }
+            },
+        )
+    }
+
+    /// The `post` layout: title, project link and article.
+    pub fn post(
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        layout(
+            theme,
+            meta,
+            html! {
+                h1 { (post.frontmatter.title)}
+                @if let Some(project) = post.project_link() {
+                    (project)
+                }
+                (series_box(navigation))
+                article {
+                    (PreEscaped(&rendered.content))
+                    (PreEscaped(&rendered.gallery))
+                }
+                (post_navigation(post, navigation))
+            },
+        )
+    }
+
+    /// The `project` layout: a post led by its tags and project link, for
+    /// write-ups of something you can go and use.
+    pub fn project(
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        layout(
+            theme,
+            meta,
+            html! {
+                header.project {
+                    h1 { (post.frontmatter.title)}
+                    ul.tags {
+                        @for (slug, name) in post.tags() {
+                            li {
+                                a href={"/tags/" (slug) ".html"} { (name) }
+                            }
+                        }
+                    }
+                    @if let Some(project) = post.project_link() {
+                        (project)
+                    }
+                }
+                (series_box(navigation))
+                article {
+                    (PreEscaped(&rendered.content))
+                    (PreEscaped(&rendered.gallery))
+                }
+                (post_navigation(post, navigation))
+            },
+        )
+    }
+
+    /// The `gallery` layout: a post led by the grid of its frontmatter
+    /// gallery, with the write-up after, for work that is mostly pictures.
+    pub fn gallery(
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        layout(
+            theme,
+            meta,
+            html! {
+                h1 { (post.frontmatter.title)}
+                @if let Some(project) = post.project_link() {
+                    (project)
                 }
+                (series_box(navigation))
+                (PreEscaped(&rendered.gallery))
+                article.gallery-notes {
+                    (PreEscaped(&rendered.content))
+                }
+                (post_navigation(post, navigation))
             },
         )
     }
 
-    pub fn layout(title: &str, content: Markup) -> Markup {
+    /// The `page` layout: a standalone page, no tags, date or project link.
+    pub fn page(
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        _: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        layout(
+            theme,
+            meta,
+            html! {
+                article.page {
+                    h1 { (post.frontmatter.title) }
+                    (PreEscaped(&rendered.content))
+                }
+            },
+        )
+    }
+
+    /// "Part N of M" and a list of every part, for posts in a series.
+    pub fn series_box(navigation: &Navigation) -> Markup {
+        html! {
+            @if let Some(ref series) = navigation.series {
+                aside.series {
+                    p {
+                        "Part " (series.part) " of " (series.parts.len()) " in "
+                        a href=(series.url) { (series.name) }
+                    }
+                    ol {
+                        @for (i, part) in series.parts.iter().enumerate() {
+                            li {
+                                @if i + 1 == series.part {
+                                    strong { (part.title) }
+                                } @else {
+                                    a href=(part.url) { (part.title) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Links to related posts, then to the older and newer posts, overall
+    /// and, when they differ, within the post's section and with each of its
+    /// tags.
+    pub fn post_navigation(post: &Post, navigation: &Navigation) -> Markup {
+        html! {
+            nav.post-nav {
+                @if !navigation.related.is_empty() {
+                    h4 { "Related" }
+                    ul.related {
+                        @for post in navigation.related.iter() {
+                            li {
+                                a href=(post.url) { (post.title) }
+                            }
+                        }
+                    }
+                }
+                (neighbour_links(&navigation.all))
+                @if let Some(ref section) = navigation.section {
+                    @if !navigation.in_section.is_empty() && navigation.in_section != navigation.all {
+                        h4 { "More in " (section) }
+                        (neighbour_links(&navigation.in_section))
+                    }
+                }
+                @for (slug, neighbours) in navigation.tags.iter() {
+                    @if !neighbours.is_empty() && *neighbours != navigation.all && *neighbours != navigation.in_section {
+                        h4 { "More tagged " (post.tag_names.get(slug).unwrap_or(slug)) }
+                        (neighbour_links(neighbours))
+                    }
+                }
+            }
+        }
+    }
+
+    fn neighbour_links(neighbours: &Neighbours) -> Markup {
+        html! {
+            div.neighbours {
+                @if let Some(ref previous) = neighbours.previous {
+                    a.previous href=(previous.url) rel="prev" {
+                        span { "Previous" }
+                        (previous.title)
+                    }
+                }
+                @if let Some(ref next) = neighbours.next {
+                    a.next href=(next.url) rel="next" {
+                        span { "Next" }
+                        (next.title)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Wraps `content` in the full page, with the pieces around it supplied
+    /// by `theme`.
+    pub fn layout(theme: &dyn Theme, meta: &Meta, content: Markup) -> Markup {
         html! {
             (DOCTYPE)
             head {
-                title {(title)}
-                (Css("/reset.css"))
-                (Css("/style.css"))
-                meta charset="utf-8";
-                (favicons())
+                (theme.head(meta))
+            }
+            (theme.header())
+            (theme.sidebar(meta))
+            div#main {
+                main {
+                    (content)
+                }
+                (theme.footer())
+            }
+            @if cfg!(debug_assertions) {
+                script src="/index.js" {}
+            }
+        }
+    }
+
+    pub fn head(meta: &Meta) -> Markup {
+        html! {
+            title {(meta.title)}
+            (Css("/reset.css"))
+            (Css("/style.css"))
+            meta charset="utf-8";
+            (meta_tags(meta))
+            (favicons())
+        }
+    }
+
+    /// The canonical URL, description, Open Graph and Twitter Card tags, and
+    /// JSON-LD for posts.
+    pub fn meta_tags(meta: &Meta) -> Markup {
+        html! {
+            link rel="canonical" href=(meta.url);
+            meta name="description" content=(meta.description);
+            meta property="og:site_name" content=(meta.site_name);
+            meta property="og:title" content=(meta.title);
+            meta property="og:description" content=(meta.description);
+            meta property="og:url" content=(meta.url);
+            @if let Some(ref image) = meta.image {
+                meta property="og:image" content=(image);
+            }
+            @if let Some(ref article) = meta.article {
+                meta property="og:type" content="article";
+                meta property="article:published_time" content=(article.published.to_rfc3339());
+                meta property="article:modified_time" content=(article.modified.to_rfc3339());
+                meta property="article:author" content=(article.author);
+                @for tag in article.tags.iter() {
+                    meta property="article:tag" content=(tag);
+                }
+            } @else {
+                meta property="og:type" content="website";
+            }
+            @if meta.image.is_some() {
+                meta name="twitter:card" content="summary_large_image";
+            } @else {
+                meta name="twitter:card" content="summary";
+            }
+            meta name="twitter:title" content=(meta.title);
+            meta name="twitter:description" content=(meta.description);
+            @if let Some(ref image) = meta.image {
+                meta name="twitter:image" content=(image);
+            }
+            @if let Some(data) = meta.json_ld_script() {
+                script type="application/ld+json" { (PreEscaped(data)) }
             }
+        }
+    }
+
+    pub fn header() -> Markup {
+        html! {
             div.buffer {}
+        }
+    }
+
+    pub fn sidebar(meta: &Meta) -> Markup {
+        html! {
             div#sidebar {
                 .tagline {
                     ul {
-                        li {
-                            a href="/tags/dev.html" {
-                                span { "dev"}
-                            }
-                        }
-                        li {
-                            a href="/tags/design.html" {
-                                span { "design"}
+                        @for link in meta.menu.iter() {
+                            li {
+                                a href=(link.url) {
+                                    span { (link.name) }
+                                }
                             }
                         }
-                        li {
-                            a href="/tags/tags.html" {
-                                span { "tags"}
+                        @for taxonomy in meta.taxonomies.iter() {
+                            li {
+                                a href=(taxonomy.url) {
+                                    span { (taxonomy.title) }
+                                }
                             }
                         }
                     }
-                    a href="/posts/about.html" { "rustacean, cook, and martial arts enthusiast"}
+                    @match meta.about {
+                        Some(ref about) => a href=(about) { (meta.tagline) },
+                        None => span { (meta.tagline) },
+                    }
                     div {}
                 }
                 a.logo href="/" {
                     img src="/DK Logo.png" { }
                 }
             }
-            div#main {
-                main {
-                    (content)
-                }
-                footer {
-                    span{"Copyright 2020"}
-                    div.links {
-                        a onClick="
+        }
+    }
+
+    pub fn footer() -> Markup {
+        html! {
+            footer {
+                span{"Copyright 2020"}
+                div.links {
+                    a onClick="
                         javascript:window.location.href=atob('bWFpbHRvOndlYi1jb250YWN0QGtub3BvZmYuZGV2')"  { img src="/mail.svg" {}}
-                        a href="https://github.com/dustinknopoff" rel="noopener noreferrer nofollow" target="_blank"{ img src="/github.svg" {}}
-                        a href="https://linkedin.com/in/dustinknopoff" { img src="/linkedin.svg" {}}
-                        a href="/feed.xml" { img src="/rss.svg" {}}
-                    }
-                    div{}
+                    a href="https://github.com/dustinknopoff" rel="noopener noreferrer nofollow" target="_blank"{ img src="/github.svg" {}}
+                    a href="https://linkedin.com/in/dustinknopoff" { img src="/linkedin.svg" {}}
+                    a href="/feed.xml" { img src="/rss.svg" {}}
                 }
-            }
-            @if cfg!(debug_assertions) {
-                script src="/index.js" {}
+                div{}
             }
         }
     }
@@ -226,73 +888,354 @@ pub mod layout {
 }
 
 pub mod pages {
-    use std::{
-        collections::HashMap,
-        fs::{DirBuilder, File},
-        io::Write,
-        path::Path,
-        path::PathBuf,
-        sync::Arc,
-    };
+    use std::collections::BTreeMap;
 
-    use maud::{html, Markup};
+    use maud::{html, Markup, PreEscaped, DOCTYPE};
 
-    use super::{
-        layout::layout,
-        markdown::{preview, Blurb},
+    use super::{gallery::thumbnails, layout::layout, markdown::preview};
+    use crate::{
+        cache::Listed,
+        config::Config,
+        meta::Meta,
+        navigation::Series,
+        output::Output,
+        tags::Tags,
+        taxonomy::{parent, Taxonomy},
+        theme::Theme,
     };
-    use crate::retrieve::Post;
 
-    pub fn write_tags_to_file(
-        tag_map: HashMap<String, Vec<Arc<Post>>>,
+    /// Writes the index of `taxonomy` and a page for each of its `terms`,
+    /// named with `names`.
+    pub fn write_taxonomy_to_file(
+        theme: &dyn Theme,
+        config: &Config,
+        taxonomy: &Taxonomy,
+        names: &Tags,
+        terms: &BTreeMap<String, Vec<Listed>>,
+        out: &mut Output,
     ) -> Result<(), anyhow::Error> {
-        if !Path::new("public/tags").exists() {
-            DirBuilder::new().recursive(true).create("public/tags")?;
-        }
-        let keys = tag_map.keys().collect::<Vec<_>>();
-        let all_tags = tags(&keys);
-        {
-            let mut file = File::create("public/tags/tags.html")?;
-            file.write_all(all_tags.into_string().as_bytes())?;
-        }
-        for key in keys {
-            let tag_html = tag(key, &tag_map[key]);
-            {
-                let mut path = PathBuf::from("public/tags");
-                path.push(key);
-                path.set_extension("html");
-                let mut file = File::create(path)?;
-                file.write_all(tag_html.into_string().as_bytes())?;
-            }
+        let index = index_of_terms(theme, config, taxonomy, names, terms);
+        out.write(
+            format!("public{}", taxonomy.index_url()),
+            index.into_string().as_bytes(),
+        )?;
+        for (key, posts) in terms.iter() {
+            let term_html = term(theme, config, taxonomy, names, key, posts);
+            out.write(
+                format!("public{}", taxonomy.url(key)),
+                term_html.into_string().as_bytes(),
+            )?;
         }
         Ok(())
     }
 
-    pub fn tags(tags: &[&String]) -> Markup {
+    /// Every term by name with how many posts use it, nested ones under
+    /// their parents. As a cloud, terms used more often are shown larger.
+    pub fn index_of_terms(
+        theme: &dyn Theme,
+        config: &Config,
+        taxonomy: &Taxonomy,
+        names: &Tags,
+        terms: &BTreeMap<String, Vec<Listed>>,
+    ) -> Markup {
+        let mut counts = terms
+            .iter()
+            .map(|(term, posts)| (term.as_str(), posts.len()))
+            .collect::<Vec<_>>();
+        counts.sort_by_cached_key(|&(term, _)| (names.name(term).to_lowercase(), term));
+        let weights = if taxonomy.cloud {
+            let fewest = counts.iter().map(|&(_, count)| count).min();
+            let most = counts.iter().map(|&(_, count)| count).max();
+            fewest.zip(most)
+        } else {
+            None
+        };
+        let title = format!("{} | {}", config.title, taxonomy.title());
         layout(
-            "Dustin Knopoff | Tags",
+            theme,
+            &Meta::page(config, &title, &taxonomy.index_url()),
             html! {
-                h3 { "Tags"}
-                @for tag in tags {
-                    a href={"/tags/" (tag) ".html"} {
-                        li { (tag)}
-                    }
+                h3 { (taxonomy.title()) }
+                ul.tag-index.tag-cloud[taxonomy.cloud] {
+                    (term_list(taxonomy, names, &counts, None, weights))
                 }
             },
         )
     }
 
-    pub fn tag(tag: &str, posts: &[Arc<Post>]) -> Markup {
+    /// The entries in `counts` directly `under` a term, or at the top, each
+    /// followed by its own.
+    fn term_list(
+        taxonomy: &Taxonomy,
+        names: &Tags,
+        counts: &[(&str, usize)],
+        under: Option<&str>,
+        weights: Option<(usize, usize)>,
+    ) -> Markup {
+        html! {
+            @for &(term, count) in counts.iter().filter(|&&(term, _)| parent(term) == under) {
+                @let entry = html! {
+                    a href=(taxonomy.url(term)) { (names.name(term)) }
+                    " "
+                    span.count { (count) }
+                    @let nested = term_list(taxonomy, names, counts, Some(term), weights);
+                    @if !nested.0.is_empty() {
+                        ul { (nested) }
+                    }
+                };
+                @if let Some((fewest, most)) = weights {
+                    li class={"weight-" (weight(count, fewest, most))} { (entry) }
+                } @else {
+                    li { (entry) }
+                }
+            }
+        }
+    }
+
+    /// Where `count` falls between `fewest` and `most` on a log scale, from
+    /// 1 to 5.
+    fn weight(count: usize, fewest: usize, most: usize) -> usize {
+        if most <= fewest {
+            return 3;
+        }
+        let ln = |n: usize| (n.max(1) as f64).ln();
+        let scale = (ln(count) - ln(fewest)) / (ln(most) - ln(fewest));
+        1 + (scale * 4.0).round() as usize
+    }
+
+    /// Every post filed under `term`, with a link to its feed.
+    pub fn term(
+        theme: &dyn Theme,
+        config: &Config,
+        taxonomy: &Taxonomy,
+        names: &Tags,
+        term: &str,
+        posts: &[Listed],
+    ) -> Markup {
+        let name = names.name(term);
+        let title = format!("{} | {}", config.title, name);
+        let meta = Meta {
+            description: names
+                .description(term)
+                .map_or_else(|| config.description.clone(), String::from),
+            ..Meta::page(config, &title, &taxonomy.url(term))
+        };
         layout(
-            &format!("Dustin Knopoff | {}", tag),
+            theme,
+            &meta,
             html! {
-                h3 { (tag)}
+                h3 { (name)}
+                @if let Some(parent) = parent(term) {
+                    p.term-parent {
+                        "In " a href=(taxonomy.url(parent)) { (names.name(parent)) }
+                    }
+                }
+                @if let Some(description) = names.description(term) {
+                    p.tag-description { (description) }
+                }
+                p.term-feed {
+                    a href=(taxonomy.feed_url(term)) { "Feed" }
+                }
                 @for post in posts {
-                   ( preview(&post.frontmatter.title, Some(&post.frontmatter.tags), html! {
-                       (Blurb(&post.content))
-                   }, post.filename.to_public_path(false).0.to_str().unwrap()))
+                   ( preview(&post.frontmatter.title, Some(&post.tags()), html! {
+                       (PreEscaped(&post.rendered.blurb))
+                   }, post.public_path(false).0.to_str().unwrap(), post.project_link(),
+                   thumbnails(config, post)))
                 }
             },
         )
     }
+
+    pub fn write_series_to_file(
+        theme: &dyn Theme,
+        config: &Config,
+        series: &BTreeMap<String, Vec<Listed>>,
+        out: &mut Output,
+    ) -> Result<(), anyhow::Error> {
+        for (name, parts) in series.iter() {
+            let path = format!("public{}", Series::url(name));
+            out.write(
+                path,
+                series_page(theme, config, name, parts)
+                    .into_string()
+                    .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Every part of the series `name`, in order.
+    pub fn series_page(theme: &dyn Theme, config: &Config, name: &str, parts: &[Listed]) -> Markup {
+        let title = format!("{} | {}", config.title, name);
+        layout(
+            theme,
+            &Meta::page(config, &title, &Series::url(name)),
+            html! {
+                h3 { (name) }
+                ol.series-parts {
+                    @for post in parts {
+                        li {
+                            ( preview(&post.frontmatter.title, Some(&post.tags()), html! {
+                                (PreEscaped(&post.rendered.blurb))
+                            }, post.public_path(false).0.to_str().unwrap(), post.project_link(),
+                            thumbnails(config, post)))
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// A stand-in at an old URL of the page now at the site path `to`, which
+    /// sends browsers and search engines on to it.
+    pub fn redirect(config: &Config, to: &str) -> Markup {
+        let url = config.url(to);
+        html! {
+            (DOCTYPE)
+            html {
+                head {
+                    meta charset="utf-8";
+                    title { "Moved to " (url) }
+                    link rel="canonical" href=(url);
+                    meta name="robots" content="noindex";
+                    meta http-equiv="refresh" content={"0; url=" (to)};
+                }
+                body {
+                    p { "This page has moved to " a href=(to) { (url) } "." }
+                }
+            }
+        }
+    }
+}
+
+/// The social preview image of a post, as SVG for resvg to draw.
+pub mod card {
+    use maud::{html, Markup};
+
+    use crate::retrieve::Post;
+
+    pub const WIDTH: u32 = 1200;
+    pub const HEIGHT: u32 = 630;
+    /// Where the logo is, relative to the resources directory.
+    pub const LOGO: &str = "DK Logo.svg";
+    /// Characters of the title that fit on one line.
+    const LINE_LENGTH: usize = 28;
+    const MAX_LINES: usize = 3;
+    const TITLE_FONT: &str = "Publico Headline, Helvetica, Arial, DejaVu Sans, sans-serif";
+    const TEXT_FONT: &str = "Graphik, Helvetica, Arial, DejaVu Sans, sans-serif";
+    const MONO_FONT: &str = "Courier, DejaVu Sans Mono, monospace";
+
+    /// The title, tags and date of `post` beside the logo, signed with
+    /// `site`.
+    pub fn card(post: &Post, site: &str) -> Markup {
+        let lines = wrap(&post.frontmatter.title, LINE_LENGTH, MAX_LINES);
+        let tags = post
+            .tags()
+            .into_iter()
+            .map(|(_, name)| format!("#{}", name))
+            .collect::<Vec<_>>();
+        html! {
+            svg xmlns="http://www.w3.org/2000/svg" width=(WIDTH) height=(HEIGHT)
+                viewBox={"0 0 " (WIDTH) " " (HEIGHT)} {
+                rect width=(WIDTH) height=(HEIGHT) fill="#ffffff" {}
+                rect y=(HEIGHT - 16) width=(WIDTH) height="16" fill="#0a648f" {}
+                image href=(LOGO) x="1010" y="60" width="130" height="206" {}
+                text font-family=(TITLE_FONT) font-size="64" font-weight="bold" fill="#212121" {
+                    @for (i, line) in lines.iter().enumerate() {
+                        tspan x="80" y=(170 + 80 * i) { (line) }
+                    }
+                }
+                text x="80" y="480" font-family=(MONO_FONT) font-size="32" fill="#7b3634" {
+                    (tags.join(" "))
+                }
+                text x="80" y="560" font-family=(TEXT_FONT) font-size="28" fill="#6d696d" {
+                    (post.date.format("%B %-d, %Y"))
+                }
+                text x="1120" y="560" text-anchor="end" font-family=(TEXT_FONT) font-size="28"
+                    fill="#6d696d" {
+                    (site)
+                }
+            }
+        }
+    }
+
+    /// `text` broken into lines of at most `length` characters at spaces,
+    /// the last of `max` lines ending in an ellipsis when there is more.
+    fn wrap(text: &str, length: usize, max: usize) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for word in text.split_whitespace() {
+            let fits = lines
+                .last()
+                .map(|line| line.chars().count() + 1 + word.chars().count() <= length)
+                .unwrap_or(false);
+            if fits {
+                let line = lines.last_mut().unwrap();
+                line.push(' ');
+                line.push_str(word);
+            } else if lines.len() == max {
+                let last = lines.last_mut().unwrap();
+                while last.chars().count() + 1 > length {
+                    last.pop();
+                }
+                last.push('…');
+                break;
+            } else {
+                lines.push(word.to_string());
+            }
+        }
+        lines
+    }
+}
+
+/// Image galleries, and the thumbnails of them shown in listings.
+pub mod gallery {
+    use maud::{html, Markup};
+
+    use crate::{
+        config::{CloudinaryConfig, Config},
+        gallery::{self, GalleryImage, THUMBNAIL},
+        retrieve::Post,
+    };
+
+    /// Thumbnails shown in a post's preview in listings, at most.
+    const PREVIEW_THUMBNAILS: usize = 4;
+
+    /// A grid of square thumbnails, each linking to its image at full size
+    /// for a lightbox script to pick up.
+    pub fn grid(images: &[GalleryImage], cloudinary: Option<&CloudinaryConfig>) -> Markup {
+        html! {
+            div.gallery {
+                @for image in images {
+                    @let alt = image.alt.as_deref().unwrap_or_default();
+                    figure.gallery-item {
+                        a.gallery-link href=(image.full(cloudinary)) title=(image.caption.as_deref().unwrap_or(alt)) {
+                            img src=(image.thumbnail(cloudinary)) alt=(alt)
+                                width=(THUMBNAIL) height=(THUMBNAIL) loading="lazy" decoding="async" {}
+                        }
+                        @if let Some(ref caption) = image.caption {
+                            figcaption { (caption) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The first few images of `post`'s galleries, small, for its preview.
+    pub fn thumbnails(config: &Config, post: &Post) -> Markup {
+        let images = gallery::images(post);
+        html! {
+            @if !images.is_empty() {
+                ul.thumbnails {
+                    @for image in images.iter().take(PREVIEW_THUMBNAILS) {
+                        @let alt = image.alt.as_deref().unwrap_or_default();
+                        li {
+                            img src=(image.thumbnail(Some(&config.cloudinary))) alt=(alt)
+                                width=(THUMBNAIL) height=(THUMBNAIL) loading="lazy" decoding="async" {}
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
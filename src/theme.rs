@@ -0,0 +1,213 @@
+//! Extension points for the site's markup.
+//!
+//! A [`Theme`] supplies the pieces wrapped around every page and can register
+//! extra named [`Layout`]s. Posts pick a layout with the `layout` frontmatter
+//! key, falling back to `post` or `page`.
+//!
+//! ```
+//! use maud::{html, Markup};
+//! use portfolio_rs::{
+//!     meta::Meta,
+//!     navigation::Navigation,
+//!     templates::layout,
+//!     theme::{Layouts, Theme},
+//!     Post, Rendered,
+//! };
+//!
+//! struct Mine;
+//!
+//! fn talk(
+//!     theme: &dyn Theme,
+//!     post: &Post,
+//!     _: &Rendered,
+//!     _: &Navigation,
+//!     meta: &Meta,
+//! ) -> Markup {
+//!     layout::layout(theme, meta, html! {
+//!         h1 { "Talk: " (post.frontmatter.title) }
+//!     })
+//! }
+//!
+//! impl Theme for Mine {
+//!     fn footer(&self) -> Markup {
+//!         html! { footer { "Made by us" } }
+//!     }
+//!
+//!     fn register_layouts(&self, layouts: &mut Layouts) {
+//!         layouts.register("talk", talk);
+//!     }
+//! }
+//! ```
+//!
+//! Build with it through `Pipeline::with_theme`.
+use crate::{
+    meta::Meta,
+    navigation::Navigation,
+    persist::Rendered,
+    retrieve::{Kind, Post},
+    templates::layout,
+};
+use maud::Markup;
+use std::{collections::BTreeMap, fmt};
+
+/// The parts of the page shared by every layout. Each method defaults to the
+/// built-in markup, so implementors only override what they change.
+pub trait Theme: Send + Sync {
+    /// Contents of `<head>`.
+    fn head(&self, meta: &Meta) -> Markup {
+        layout::head(meta)
+    }
+
+    /// Rendered before the sidebar.
+    fn header(&self) -> Markup {
+        layout::header()
+    }
+
+    fn sidebar(&self, meta: &Meta) -> Markup {
+        layout::sidebar(meta)
+    }
+
+    fn footer(&self) -> Markup {
+        layout::footer()
+    }
+
+    /// Adds or replaces named layouts. Called once, after the built-in ones
+    /// are registered.
+    fn register_layouts(&self, _layouts: &mut Layouts) {}
+}
+
+/// The theme used when none is given.
+#[derive(Debug, Default)]
+pub struct DefaultTheme;
+
+impl Theme for DefaultTheme {}
+
+/// Renders a whole page for a post.
+pub trait Layout: Send + Sync {
+    fn render(
+        &self,
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup;
+}
+
+impl<F> Layout for F
+where
+    F: Fn(&dyn Theme, &Post, &Rendered, &Navigation, &Meta) -> Markup + Send + Sync,
+{
+    fn render(
+        &self,
+        theme: &dyn Theme,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        self(theme, post, rendered, navigation, meta)
+    }
+}
+
+/// Layouts by the name used in frontmatter.
+#[derive(Default)]
+pub struct Layouts(BTreeMap<String, Box<dyn Layout>>);
+
+impl Layouts {
+    pub fn register<L: Layout + 'static>(&mut self, name: &str, layout: L) {
+        self.0.insert(name.to_string(), Box::new(layout));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Layout> {
+        self.0.get(name).map(|layout| layout.as_ref())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// A theme together with every layout available to posts.
+pub struct Templates {
+    theme: Box<dyn Theme>,
+    layouts: Layouts,
+    /// Tells this theme and its layouts apart from others, for cache keys.
+    identity: String,
+}
+
+impl Templates {
+    pub fn new<T: Theme + 'static>(theme: T) -> Self {
+        let mut layouts = Layouts::default();
+        layouts.register("post", layout::post);
+        layouts.register("project", layout::project);
+        layouts.register("page", layout::page);
+        layouts.register("gallery", layout::gallery);
+        theme.register_layouts(&mut layouts);
+        let identity = std::iter::once(std::any::type_name::<T>())
+            .chain(layouts.names())
+            .collect::<Vec<_>>()
+            .join(",");
+        Templates {
+            theme: Box::new(theme),
+            layouts,
+            identity,
+        }
+    }
+
+    /// The theme's type and the names of its layouts.
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    pub fn theme(&self) -> &dyn Theme {
+        self.theme.as_ref()
+    }
+
+    pub fn layouts(&self) -> &Layouts {
+        &self.layouts
+    }
+
+    /// Name of the layout `post` asked for, or the default for its kind.
+    pub fn layout_name(post: &Post) -> &str {
+        match (&post.frontmatter.layout, post.kind()) {
+            (Some(name), _) => name,
+            (None, Kind::Post) => "post",
+            (None, Kind::Page) => "page",
+        }
+    }
+
+    /// Renders `post` with its layout. Unknown layouts fall back to the
+    /// default for the post's kind; `check` reports them.
+    pub fn render(
+        &self,
+        post: &Post,
+        rendered: &Rendered,
+        navigation: &Navigation,
+        meta: &Meta,
+    ) -> Markup {
+        let name = Templates::layout_name(post);
+        let layout = self.layouts.get(name).unwrap_or_else(|| {
+            log::warn!("Unknown layout `{}` in {}", name, post.filename.0.display());
+            let fallback = if post.is_page() { "page" } else { "post" };
+            self.layouts
+                .get(fallback)
+                .expect("built-in layouts are always registered")
+        });
+        layout.render(self.theme(), post, rendered, navigation, meta)
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Templates::new(DefaultTheme)
+    }
+}
+
+impl fmt::Debug for Templates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Templates")
+            .field("layouts", &self.layouts.names().collect::<Vec<_>>())
+            .finish()
+    }
+}